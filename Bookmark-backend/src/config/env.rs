@@ -6,6 +6,8 @@ pub struct EnvConfig {
     pub db_name: String,
     pub jwt_secret: String,
     pub allowed_origins: Vec<String>,
+    pub max_upload_bytes: usize,
+    pub public_url: String,
 }
 
 impl EnvConfig {
@@ -32,6 +34,16 @@ impl EnvConfig {
                 .expect("JWT_SECRET missing"),
 
             allowed_origins,
+
+            max_upload_bytes: env::var("MAX_UPLOAD_MB")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(20) * 1024 * 1024,
+
+            public_url: env::var("PUBLIC_URL")
+                .unwrap_or("http://localhost:3000".to_string())
+                .trim_end_matches('/')
+                .to_string(),
         }
     }
 }
\ No newline at end of file
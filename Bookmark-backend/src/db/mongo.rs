@@ -1,8 +1,11 @@
 use mongodb::{Client, Database, IndexModel};
-use mongodb::bson::doc;
+use mongodb::bson::{doc, Bson};
+use futures::TryStreamExt;
 use mongodb::options::IndexOptions;
 
 use crate::config::env::EnvConfig;
+use crate::handlers::import::PREVIEW_TTL;
+use crate::utils::tags::normalize_stored_tags;
 
 pub async fn connect(config: &EnvConfig) -> Database {
 
@@ -20,25 +23,210 @@ pub async fn connect(config: &EnvConfig) -> Database {
 
     let db = client.database(&config.db_name);
 
+    migrate_collection_ids(&db).await;
+
+    migrate_tags(&db).await;
+
     // Create text index for search
     create_indexes(&db).await;
 
+    // Needs the unique tag index
+    migrate_tag_metadata(&db).await;
+
     db
 }
 
+// Transactions need a replica set or a sharded cluster
+pub async fn supports_transactions(db: &Database) -> bool {
+
+    let hello = match db.run_command(doc! { "hello": 1 }, None).await {
+        Ok(hello) => hello,
+        Err(_) => return false,
+    };
+
+    hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid")
+}
+
+// Bookmarks used to be in at most one collection, with a single position.
+// Moves collection_id into collection_ids and position into positions,
+// keyed by the collection or "unfiled". Already migrated bookmarks are skipped
+async fn migrate_collection_ids(db: &Database) {
+
+    let bookmarks = db.collection::<mongodb::bson::Document>("bookmarks");
+
+    let filed = doc! { "$eq": [{ "$type": "$collection_id" }, "objectId"] };
+
+    let pipeline = vec![
+        doc! { "$set": {
+            "collection_ids": { "$cond": [&filed, ["$collection_id"], []] },
+            "positions": { "$cond": [
+                { "$eq": [{ "$type": "$position" }, "string"] },
+                { "$arrayToObject": [[{
+                    "k": { "$cond": [&filed, { "$toString": "$collection_id" }, "unfiled"] },
+                    "v": "$position"
+                }]] },
+                {}
+            ] }
+        } },
+        doc! { "$unset": ["collection_id", "position"] },
+    ];
+
+    let result = bookmarks
+        .update_many(doc! { "collection_ids": { "$exists": false } }, pipeline, None)
+        .await
+        .expect("Failed to migrate bookmark collections");
+
+    if result.modified_count > 0 {
+        println!("Migrated {} bookmarks to multiple collections", result.modified_count);
+    }
+
+    // Replaced by bookmark_collections_index
+    let _ = bookmarks.drop_index("bookmark_collection_index", None).await;
+}
+
+// Tags used to be stored as sent. Normalizes all existing ones once,
+// runs that already happened are recorded in the migrations collection
+async fn migrate_tags(db: &Database) {
+
+    let migrations = db.collection::<mongodb::bson::Document>("migrations");
+
+    let done = migrations
+        .find_one(doc! { "_id": "normalize_tags" }, None)
+        .await
+        .expect("Failed to read migrations");
+
+    if done.is_some() {
+        return;
+    }
+
+    let updated = normalize_stored_tags(db, doc! {})
+        .await
+        .expect("Failed to normalize tags");
+
+    migrations
+        .insert_one(doc! { "_id": "normalize_tags", "ran_at": chrono::Utc::now().to_rfc3339() }, None)
+        .await
+        .expect("Failed to record migration");
+
+    if updated > 0 {
+        println!("Normalized the tags of {} bookmarks", updated);
+    }
+}
+
+// Tags only used to exist on bookmarks. Creates the metadata of every tag
+// in use once, dated by the first bookmark it was on
+async fn migrate_tag_metadata(db: &Database) {
+
+    let migrations = db.collection::<mongodb::bson::Document>("migrations");
+
+    let done = migrations
+        .find_one(doc! { "_id": "tag_metadata" }, None)
+        .await
+        .expect("Failed to read migrations");
+
+    if done.is_some() {
+        return;
+    }
+
+    let pipeline = vec![
+        doc! { "$unwind": "$tags" },
+        doc! { "$group": {
+            "_id": { "user_id": "$user_id", "name": "$tags" },
+            "created_at": { "$min": "$created_at" }
+        } },
+        doc! { "$project": {
+            "_id": 0,
+            "user_id": "$_id.user_id",
+            "name": "$_id.name",
+            "pinned": { "$literal": false },
+            "created_at": 1,
+            "updated_at": "$created_at"
+        } },
+        doc! { "$merge": {
+            "into": "tags",
+            "on": ["user_id", "name"],
+            "whenMatched": "keepExisting",
+            "whenNotMatched": "insert"
+        } },
+    ];
+
+    db.collection::<mongodb::bson::Document>("bookmarks")
+        .aggregate(pipeline, None)
+        .await
+        .expect("Failed to create tag metadata");
+
+    migrations
+        .insert_one(doc! { "_id": "tag_metadata", "ran_at": chrono::Utc::now().to_rfc3339() }, None)
+        .await
+        .expect("Failed to record migration");
+}
+
+// Drops bookmark_text_index when its fields or weights differ from the wanted ones
+async fn drop_outdated_text_index(bookmarks: &mongodb::Collection<mongodb::bson::Document>, weights: &mongodb::bson::Document) {
+
+    let Ok(cursor) = bookmarks.list_indexes(None).await else {
+        return;
+    };
+
+    let indexes: Vec<IndexModel> = cursor.try_collect().await.unwrap_or_default();
+
+    let existing = indexes
+        .into_iter()
+        .filter_map(|index| index.options)
+        .find(|options| options.name.as_deref() == Some("bookmark_text_index"));
+
+    let Some(existing) = existing else {
+        return;
+    };
+
+    let current = existing.weights.unwrap_or_default();
+
+    // Weights come back as any number type
+    let number = |value: &Bson| match value {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    };
+
+    let same = current.len() == weights.len()
+        && weights.iter().all(|(field, weight)| current.get(field).and_then(number) == number(weight));
+
+    if !same {
+        bookmarks
+            .drop_index("bookmark_text_index", None)
+            .await
+            .expect("Failed to drop old text index");
+        println!("Replacing the bookmark text index");
+    }
+}
+
 async fn create_indexes(db: &Database) {
 
     let bookmarks = db.collection::<mongodb::bson::Document>("bookmarks");
 
+    // Relevance of a match in each field, a title match counts most
+    let weights = doc! {
+        "title": 10,
+        "tags": 5,
+        "description": 3,
+        "url": 1
+    };
+
+    // A collection has at most one text index, an older one is replaced
+    drop_outdated_text_index(&bookmarks, &weights).await;
+
     let index = IndexModel::builder()
         .keys(doc! {
             "title": "text",
+            "tags": "text",
             "description": "text",
             "url": "text"
         })
         .options(
             IndexOptions::builder()
                 .name("bookmark_text_index".to_string())
+                .weights(weights)
                 .build()
         )
         .build();
@@ -48,5 +236,193 @@ async fn create_indexes(db: &Database) {
         .await
         .expect("Failed to create text index");
 
+    // Collection contents and counts
+    let index = IndexModel::builder()
+        .keys(doc! { "collection_ids": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("bookmark_collections_index".to_string())
+                .build()
+        )
+        .build();
+
+    bookmarks
+        .create_index(index, None)
+        .await
+        .expect("Failed to create collection index");
+
+    // One membership per user and collection
+    let members = db.collection::<mongodb::bson::Document>("collection_members");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "collection_id": 1, "user_id": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("collection_member_index".to_string())
+                .unique(true)
+                .build()
+        )
+        .build();
+
+    members
+        .create_index(index, None)
+        .await
+        .expect("Failed to create member index");
+
+    // Public links are looked up by slug
+    let collections = db.collection::<mongodb::bson::Document>("collections");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "share.slug": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("collection_share_index".to_string())
+                .unique(true)
+                .sparse(true)
+                .build()
+        )
+        .build();
+
+    collections
+        .create_index(index, None)
+        .await
+        .expect("Failed to create share index");
+
+    // Sub collections of a shared collection are found by parent
+    let index = IndexModel::builder()
+        .keys(doc! { "parent_id": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("collection_parent_index".to_string())
+                .build()
+        )
+        .build();
+
+    collections
+        .create_index(index, None)
+        .await
+        .expect("Failed to create parent index");
+
+    // Wrong passwords of public links, counted per link and time window
+    let share_attempts = db.collection::<mongodb::bson::Document>("share_attempts");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "slug": 1, "window": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("share_attempt_index".to_string())
+                .unique(true)
+                .build()
+        )
+        .build();
+
+    share_attempts
+        .create_index(index, None)
+        .await
+        .expect("Failed to create share attempt index");
+
+    // Counters of past windows expire
+    let index = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("share_attempt_expiry_index".to_string())
+                .expire_after(std::time::Duration::from_secs(0))
+                .build()
+        )
+        .build();
+
+    share_attempts
+        .create_index(index, None)
+        .await
+        .expect("Failed to create share attempt expiry index");
+
+    // Feed readers are looked up by token
+    let feeds = db.collection::<mongodb::bson::Document>("feeds");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "token": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("feed_token_index".to_string())
+                .unique(true)
+                .build()
+        )
+        .build();
+
+    feeds
+        .create_index(index, None)
+        .await
+        .expect("Failed to create feed index");
+
+    // One settings document per user
+    let tag_settings = db.collection::<mongodb::bson::Document>("tag_settings");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "user_id": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("tag_settings_user_index".to_string())
+                .unique(true)
+                .build()
+        )
+        .build();
+
+    tag_settings
+        .create_index(index, None)
+        .await
+        .expect("Failed to create tag settings index");
+
+    // One metadata document per user and tag
+    let tags = db.collection::<mongodb::bson::Document>("tags");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "user_id": 1, "name": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("tag_name_index".to_string())
+                .unique(true)
+                .build()
+        )
+        .build();
+
+    tags
+        .create_index(index, None)
+        .await
+        .expect("Failed to create tag index");
+
+    // Stored dry run entries are read back in file order by job
+    let import_items = db.collection::<mongodb::bson::Document>("import_items");
+
+    let index = IndexModel::builder()
+        .keys(doc! { "job_id": 1, "position": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("import_item_job_index".to_string())
+                .build()
+        )
+        .build();
+
+    import_items
+        .create_index(index, None)
+        .await
+        .expect("Failed to create import item index");
+
+    // Entries of previews that are never committed or cancelled expire
+    let index = IndexModel::builder()
+        .keys(doc! { "created_at": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("import_item_expiry_index".to_string())
+                .expire_after(PREVIEW_TTL)
+                .build()
+        )
+        .build();
+
+    import_items
+        .create_index(index, None)
+        .await
+        .expect("Failed to create import item expiry index");
+
     println!("Indexes created successfully");
 }
\ No newline at end of file
@@ -1,22 +1,70 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::{State, Path},
+    extract::{State, Path, Query},
     http::StatusCode,
     Json,
     Extension,
 };
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
 use chrono::Utc;
 use validator::Validate;
 use futures::TryStreamExt;
+use serde::Deserialize;
 
 use crate::state::app_state::AppState;
 use crate::models::bookmark::{
     Bookmark,
     CreateBookmarkRequest,
     UpdateBookmarkRequest,
+    ReorderBookmarkRequest,
+    MoveBookmarkRequest,
+    AddToCollectionRequest,
     BookmarkResponse,
+    RepairResponse,
 };
+use crate::models::collection::{Collection, CollectionKind};
+use crate::models::member::MemberRole;
+use crate::handlers::member::{require_role, bookmark_access, collection_role, collection_roles};
 use crate::utils::jwt::Claims;
+use crate::utils::filter::{BookmarkFilter, bookmark_filter};
+use crate::utils::tags::{load_tag_settings, normalize_tags, register_tags};
+use crate::utils::ordering::{
+    SortOrder,
+    bookmark_sort,
+    bookmark_scope,
+    bookmark_list_key,
+    append_position,
+    position_after,
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct ListBookmarksQuery {
+    pub sort: Option<SortOrder>,
+
+    #[serde(flatten)]
+    pub filter: BookmarkFilter,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepairQuery {
+    // Only report what would be repaired
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+
+// Smart collections show bookmarks by their rule, nothing can be filed in them
+fn check_filable(col: &Collection) -> Result<(), (StatusCode, String)> {
+
+    if col.kind == CollectionKind::Smart {
+        return Err((StatusCode::BAD_REQUEST, "Bookmarks cannot be added to a smart collection".to_string()));
+    }
+
+    Ok(())
+}
 
 
 // Create bookmark
@@ -33,26 +81,32 @@ pub async fn create_bookmark(
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
-    let collection_id = match &body.collection_id {
-        Some(id) => Some(ObjectId::parse_str(id)
-            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?),
-        None => None,
-    };
+    // Adding to a collection needs at least editor access to it
+    let collection_ids = filable_ids(&state, user_id, user_id, &body.collections()).await?;
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
 
     let now = Utc::now();
 
-    let bookmark = Bookmark {
+    let mut bookmark = Bookmark {
         id: None,
         user_id,
         title: body.title,
         url: body.url,
         description: body.description,
-        tags: body.tags,
-        collection_id,
+        tags: normalize_tags(&body.tags, &settings),
+        collection_ids: Vec::new(),
+        keyword: body.keyword,
+        positions: HashMap::new(),
         created_at: now,
         updated_at: now,
     };
 
+    // New bookmarks go to the end of the manual order of each list
+    let (collection_ids, positions) = memberships(&state, &bookmark, collection_ids).await?;
+    bookmark.collection_ids = collection_ids;
+    bookmark.positions = positions;
+
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
     let result = collection
@@ -60,6 +114,10 @@ pub async fn create_bookmark(
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create bookmark".to_string()))?;
 
+    register_tags(&state.db, user_id, &bookmark.tags)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save tags".to_string()))?;
+
     let mut created = bookmark;
     created.id = Some(result.inserted_id.as_object_id().unwrap());
 
@@ -67,10 +125,11 @@ pub async fn create_bookmark(
 }
 
 
-// List all bookmarks for user
+// List bookmarks for user, optionally by collection or tag
 pub async fn list_bookmarks(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
+    Query(query): Query<ListBookmarksQuery>,
 ) -> Result<Json<Vec<BookmarkResponse>>, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
@@ -78,8 +137,17 @@ pub async fn list_bookmarks(
 
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
+    let filter = bookmark_filter(user_id, &query.filter)?;
+
+    // Manual order is the one of the listed collection
+    let list = filter.get_object_id("collection_ids").ok();
+
+    let options = FindOptions::builder()
+        .sort(query.sort.map(|sort| bookmark_sort(sort, list)))
+        .build();
+
     let cursor = collection
-        .find(doc! { "user_id": user_id }, None)
+        .find(filter, options)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
 
@@ -110,13 +178,7 @@ pub async fn get_bookmark(
     let bookmark_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
 
-    let collection = state.db.collection::<Bookmark>("bookmarks");
-
-    let bookmark = collection
-        .find_one(doc! { "_id": bookmark_id, "user_id": user_id }, None)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Bookmark not found".to_string()))?;
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Viewer).await?;
 
     Ok(Json(BookmarkResponse::from(bookmark)))
 }
@@ -136,6 +198,9 @@ pub async fn update_bookmark(
     let bookmark_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
 
+    // Own bookmarks, or ones in a collection the user can edit
+    let existing = bookmark_access(&state, user_id, bookmark_id, MemberRole::Editor).await?;
+
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
     // Build update document
@@ -152,27 +217,47 @@ pub async fn update_bookmark(
     if let Some(description) = &body.description {
         update_doc.insert("description", description);
     }
-    if let Some(tags) = &body.tags {
-        update_doc.insert("tags", tags);
+    // Tags follow the settings of the bookmark's owner, who may not be the editor
+    let mut tags = None;
+    if let Some(body_tags) = &body.tags {
+        let settings = load_tag_settings(&state.db, existing.user_id).await?;
+        let normalized = normalize_tags(body_tags, &settings);
+        update_doc.insert("tags", &normalized);
+        tags = Some(normalized);
     }
-    if let Some(collection_id) = &body.collection_id {
-        let col_id = ObjectId::parse_str(collection_id)
-            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
-        update_doc.insert("collection_id", col_id);
+    if let Some(keyword) = &body.keyword {
+        update_doc.insert("keyword", keyword);
+    }
+    if let Some(collection_ids) = &body.collections() {
+        let collection_ids = filable_ids(&state, user_id, existing.user_id, collection_ids).await?;
+
+        for removed in existing.collection_ids.iter().filter(|id| !collection_ids.contains(id)) {
+            check_removable(&state, user_id, &existing, *removed).await?;
+        }
+
+        let (collection_ids, positions) = memberships(&state, &existing, collection_ids).await?;
+        update_doc.insert("collection_ids", collection_ids);
+        update_doc.insert("positions", positions_doc(&positions)?);
     }
 
     collection
         .update_one(
-            doc! { "_id": bookmark_id, "user_id": user_id },
+            doc! { "_id": bookmark_id },
             doc! { "$set": update_doc },
             None,
         )
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmark".to_string()))?;
 
+    if let Some(tags) = &tags {
+        register_tags(&state.db, existing.user_id, tags)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save tags".to_string()))?;
+    }
+
     // Fetch updated bookmark
     let bookmark = collection
-        .find_one(doc! { "_id": bookmark_id, "user_id": user_id }, None)
+        .find_one(doc! { "_id": bookmark_id }, None)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
         .ok_or((StatusCode::NOT_FOUND, "Bookmark not found".to_string()))?;
@@ -194,10 +279,28 @@ pub async fn delete_bookmark(
     let bookmark_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
 
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Editor).await?;
+
+    // Others only take it out of the collections they can edit, the owner keeps it
+    if bookmark.user_id != user_id {
+        let roles = collection_roles(&state, user_id, &bookmark.collection_ids).await?;
+
+        let kept: Vec<ObjectId> = bookmark.collection_ids
+            .iter()
+            .copied()
+            .filter(|id| roles.get(id).map(|(_, role)| *role) < Some(MemberRole::Editor))
+            .collect();
+
+        let (collection_ids, positions) = memberships(&state, &bookmark, kept).await?;
+        write_memberships(&state, bookmark_id, collection_ids, positions).await?;
+
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
     let result = collection
-        .delete_one(doc! { "_id": bookmark_id, "user_id": user_id }, None)
+        .delete_one(doc! { "_id": bookmark_id }, None)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete bookmark".to_string()))?;
 
@@ -206,4 +309,464 @@ pub async fn delete_bookmark(
     }
 
     Ok(StatusCode::NO_CONTENT)
-}
\ No newline at end of file
+}
+
+// Place a bookmark after another one in one of its lists
+pub async fn reorder_bookmark(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<ReorderBookmarkRequest>,
+) -> Result<Json<BookmarkResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmark_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
+
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Viewer).await?;
+
+    let list = bookmark_list(&bookmark, body.collection_id.as_deref())?;
+    check_list_access(&state, user_id, &bookmark, list).await?;
+
+    let after_id = parse_after_id(body.after_id.as_deref(), bookmark_id)?;
+
+    let position = position_after(
+        &state.db.collection::<Document>("bookmarks"),
+        &bookmark_scope(bookmark.user_id, list),
+        "created_at",
+        bookmark_id,
+        after_id,
+    ).await?;
+
+    let mut positions = bookmark.positions.clone();
+    positions.insert(bookmark_list_key(list), position);
+
+    save_memberships(&state, bookmark_id, bookmark.collection_ids, positions).await
+}
+
+
+// Move a bookmark from one of its collections to another, after a given bookmark or last
+pub async fn move_bookmark(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<MoveBookmarkRequest>,
+) -> Result<Json<BookmarkResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmark_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
+
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Viewer).await?;
+
+    let from = bookmark_list(&bookmark, body.from_collection_id.as_deref())?;
+    check_list_access(&state, user_id, &bookmark, from).await?;
+
+    let to = match &body.collection_id {
+        Some(id) => filable_ids(&state, user_id, bookmark.user_id, std::slice::from_ref(id)).await?.first().copied(),
+        None => None,
+    };
+
+    let mut collection_ids: Vec<ObjectId> = bookmark.collection_ids
+        .iter()
+        .copied()
+        .filter(|id| Some(*id) != from)
+        .collect();
+
+    if let Some(to) = to.filter(|to| !collection_ids.contains(to)) {
+        collection_ids.push(to);
+    }
+
+    let (collection_ids, mut positions) = memberships(&state, &bookmark, collection_ids).await?;
+
+    // A bookmark still in other collections has no place in the unfiled list
+    if to.is_some() || collection_ids.is_empty() {
+        if let Some(after_id) = parse_after_id(body.after_id.as_deref(), bookmark_id)? {
+            let position = position_after(
+                &state.db.collection::<Document>("bookmarks"),
+                &bookmark_scope(bookmark.user_id, to),
+                "created_at",
+                bookmark_id,
+                Some(after_id),
+            ).await?;
+            positions.insert(bookmark_list_key(to), position);
+        }
+    }
+
+    save_memberships(&state, bookmark_id, collection_ids, positions).await
+}
+
+
+// Add a bookmark to one more collection
+pub async fn add_to_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<AddToCollectionRequest>,
+) -> Result<Json<BookmarkResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmark_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
+
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Editor).await?;
+
+    let collection_id = filable_ids(&state, user_id, bookmark.user_id, std::slice::from_ref(&body.collection_id)).await?[0];
+
+    if bookmark.collection_ids.contains(&collection_id) {
+        return Err((StatusCode::CONFLICT, "Bookmark is already in this collection".to_string()));
+    }
+
+    let mut collection_ids = bookmark.collection_ids.clone();
+    collection_ids.push(collection_id);
+
+    let (collection_ids, mut positions) = memberships(&state, &bookmark, collection_ids).await?;
+
+    if let Some(after_id) = parse_after_id(body.after_id.as_deref(), bookmark_id)? {
+        let position = position_after(
+            &state.db.collection::<Document>("bookmarks"),
+            &bookmark_scope(bookmark.user_id, Some(collection_id)),
+            "created_at",
+            bookmark_id,
+            Some(after_id),
+        ).await?;
+        positions.insert(bookmark_list_key(Some(collection_id)), position);
+    }
+
+    save_memberships(&state, bookmark_id, collection_ids, positions).await
+}
+
+
+// Take a bookmark out of one collection, it stays in its others
+pub async fn remove_from_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((id, collection_id)): Path<(String, String)>,
+) -> Result<Json<BookmarkResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmark_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
+
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Viewer).await?;
+
+    let collection_id = bookmark_list(&bookmark, Some(&collection_id))?.unwrap();
+    check_removable(&state, user_id, &bookmark, collection_id).await?;
+
+    let collection_ids: Vec<ObjectId> = bookmark.collection_ids
+        .iter()
+        .copied()
+        .filter(|id| *id != collection_id)
+        .collect();
+
+    let (collection_ids, positions) = memberships(&state, &bookmark, collection_ids).await?;
+
+    save_memberships(&state, bookmark_id, collection_ids, positions).await
+}
+
+
+fn parse_after_id(after_id: Option<&str>, bookmark_id: ObjectId) -> Result<Option<ObjectId>, (StatusCode, String)> {
+
+    let Some(after_id) = after_id else {
+        return Ok(None);
+    };
+
+    let after_id = ObjectId::parse_str(after_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid after_id".to_string()))?;
+
+    if after_id == bookmark_id {
+        return Err((StatusCode::BAD_REQUEST, "A bookmark cannot be placed after itself".to_string()));
+    }
+
+    Ok(Some(after_id))
+}
+
+
+// Collections a bookmark can be filed in, each needing at least editor access.
+// Someone else's bookmark also has to stay where its owner can see it
+async fn filable_ids(
+    state: &AppState,
+    user_id: ObjectId,
+    owner_id: ObjectId,
+    ids: &[String],
+) -> Result<Vec<ObjectId>, (StatusCode, String)> {
+
+    let mut collection_ids: Vec<ObjectId> = Vec::new();
+
+    for id in ids {
+        let col_id = ObjectId::parse_str(id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+        if collection_ids.contains(&col_id) {
+            continue;
+        }
+
+        let (col, _) = require_role(state, user_id, col_id, MemberRole::Editor).await?;
+        check_filable(&col)?;
+
+        if owner_id != user_id && collection_role(state, owner_id, col_id).await?.is_none() {
+            return Err((StatusCode::FORBIDDEN, "The bookmark's owner has no access to this collection".to_string()));
+        }
+
+        collection_ids.push(col_id);
+    }
+
+    Ok(collection_ids)
+}
+
+
+// Owners can always take their bookmarks out of a collection, others need editor access
+async fn check_removable(
+    state: &AppState,
+    user_id: ObjectId,
+    bookmark: &Bookmark,
+    collection_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+
+    if bookmark.user_id != user_id {
+        require_role(state, user_id, collection_id, MemberRole::Editor).await?;
+    }
+
+    Ok(())
+}
+
+
+// Reordering in a collection needs editor access to it, the unfiled list is the owner's
+async fn check_list_access(
+    state: &AppState,
+    user_id: ObjectId,
+    bookmark: &Bookmark,
+    list: Option<ObjectId>,
+) -> Result<(), (StatusCode, String)> {
+
+    match list {
+        Some(collection_id) => require_role(state, user_id, collection_id, MemberRole::Editor).await.map(|_| ()),
+        None if bookmark.user_id == user_id => Ok(()),
+        None => Err((StatusCode::FORBIDDEN, "Insufficient permissions".to_string())),
+    }
+}
+
+
+// List of a bookmark a reorder or move applies to: the given collection,
+// which it must be in, else its only collection, else the unfiled list
+fn bookmark_list(bookmark: &Bookmark, collection_id: Option<&str>) -> Result<Option<ObjectId>, (StatusCode, String)> {
+
+    match collection_id {
+        Some(id) => {
+            let col_id = ObjectId::parse_str(id)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+            if !bookmark.collection_ids.contains(&col_id) {
+                return Err((StatusCode::BAD_REQUEST, "Bookmark is not in this collection".to_string()));
+            }
+
+            Ok(Some(col_id))
+        }
+        None => match bookmark.collection_ids.as_slice() {
+            [] => Ok(None),
+            [only] => Ok(Some(*only)),
+            _ => Err((StatusCode::BAD_REQUEST, "collection_id required for a bookmark in several collections".to_string())),
+        },
+    }
+}
+
+
+// Collections and positions of a bookmark once it is in exactly the given
+// collections, or unfiled when there are none. Lists it leaves drop its key,
+// lists it joins get one placing it last
+async fn memberships(
+    state: &AppState,
+    bookmark: &Bookmark,
+    collection_ids: Vec<ObjectId>,
+) -> Result<(Vec<ObjectId>, HashMap<String, String>), (StatusCode, String)> {
+
+    let docs_col = state.db.collection::<Document>("bookmarks");
+
+    let lists: Vec<Option<ObjectId>> = if collection_ids.is_empty() {
+        vec![None]
+    } else {
+        collection_ids.iter().map(|id| Some(*id)).collect()
+    };
+
+    let mut positions = HashMap::new();
+
+    for list in lists {
+        let key = bookmark_list_key(list);
+        let stays = match list {
+            Some(id) => bookmark.collection_ids.contains(&id),
+            None => bookmark.collection_ids.is_empty(),
+        };
+
+        let position = match bookmark.positions.get(&key) {
+            Some(position) if stays => position.clone(),
+            _ => append_position(&docs_col, &bookmark_scope(bookmark.user_id, list)).await?,
+        };
+        positions.insert(key, position);
+    }
+
+    Ok((collection_ids, positions))
+}
+
+
+fn positions_doc(positions: &HashMap<String, String>) -> Result<Document, (StatusCode, String)> {
+    mongodb::bson::to_document(positions)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmark".to_string()))
+}
+
+
+// Save the collections and positions of a bookmark, and return it
+async fn save_memberships(
+    state: &AppState,
+    bookmark_id: ObjectId,
+    collection_ids: Vec<ObjectId>,
+    positions: HashMap<String, String>,
+) -> Result<Json<BookmarkResponse>, (StatusCode, String)> {
+
+    write_memberships(state, bookmark_id, collection_ids, positions).await?;
+
+    let bookmark = state.db.collection::<Bookmark>("bookmarks")
+        .find_one(doc! { "_id": bookmark_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Bookmark not found".to_string()))?;
+
+    Ok(Json(BookmarkResponse::from(bookmark)))
+}
+
+
+async fn write_memberships(
+    state: &AppState,
+    bookmark_id: ObjectId,
+    collection_ids: Vec<ObjectId>,
+    positions: HashMap<String, String>,
+) -> Result<(), (StatusCode, String)> {
+
+    state.db.collection::<Bookmark>("bookmarks")
+        .update_one(
+            doc! { "_id": bookmark_id },
+            doc! { "$set": {
+                "collection_ids": collection_ids,
+                "positions": positions_doc(&positions)?,
+                "updated_at": Utc::now().to_rfc3339()
+            } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmark".to_string()))?;
+
+    Ok(())
+}
+
+
+// Find the user's bookmarks filed in a collection that no longer exists,
+// is smart, or belongs to someone who doesn't share it with them, and take
+// them out of it. Left over by writes before ownership checks
+pub async fn repair_bookmarks(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RepairQuery>,
+) -> Result<Json<RepairResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    // One entry per bookmark and collection it is in
+    let pipeline = vec![
+        doc! { "$match": { "user_id": user_id, "collection_ids.0": { "$exists": true } } },
+        doc! { "$unwind": "$collection_ids" },
+        doc! { "$lookup": {
+            "from": "collections",
+            "localField": "collection_ids",
+            "foreignField": "_id",
+            "as": "collection"
+        }},
+        doc! { "$project": {
+            "collection_id": "$collection_ids",
+            "collection": { "$arrayElemAt": ["$collection", 0] }
+        }},
+        // Keep everything except the user's own manual collections
+        doc! { "$match": { "$nor": [{
+            "collection.user_id": user_id,
+            "collection.kind": { "$ne": "smart" }
+        }] } },
+    ];
+
+    let cursor = bookmarks_col
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let candidates: Vec<Document> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check bookmarks".to_string()))?;
+
+    // Dangling collections of each bookmark
+    let mut dangling: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    // Access to other users' collections, checked once per collection
+    let mut reachable: HashMap<ObjectId, bool> = HashMap::new();
+
+    for candidate in candidates {
+        let (Ok(bookmark_id), Ok(collection_id)) = (candidate.get_object_id("_id"), candidate.get_object_id("collection_id")) else {
+            continue;
+        };
+
+        let ok = match candidate.get_document("collection") {
+            Err(_) => false,
+            Ok(collection) if collection.get_str("kind") == Ok("smart") => false,
+            Ok(_) => match reachable.get(&collection_id) {
+                Some(ok) => *ok,
+                None => {
+                    let ok = collection_role(&state, user_id, collection_id).await?.is_some();
+                    reachable.insert(collection_id, ok);
+                    ok
+                }
+            },
+        };
+
+        if !ok {
+            dangling.entry(bookmark_id).or_default().push(collection_id);
+        }
+    }
+
+    let mut repaired = 0;
+
+    if !query.dry_run {
+        for (bookmark_id, collection_ids) in &dangling {
+            let Some(bookmark) = bookmarks_col
+                .find_one(doc! { "_id": bookmark_id, "user_id": user_id }, None)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+            else {
+                continue;
+            };
+
+            let remaining: Vec<ObjectId> = bookmark.collection_ids
+                .iter()
+                .copied()
+                .filter(|id| !collection_ids.contains(id))
+                .collect();
+
+            let (remaining, positions) = memberships(&state, &bookmark, remaining).await?;
+            write_memberships(&state, *bookmark_id, remaining, positions).await?;
+
+            repaired += 1;
+        }
+    }
+
+    Ok(Json(RepairResponse {
+        dangling: dangling.len() as u64,
+        repaired,
+        bookmark_ids: dangling.keys().map(|id| id.to_hex()).collect(),
+    }))
+}
@@ -1,13 +1,19 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::{State, Path},
+    extract::{State, Path, Query},
     http::StatusCode,
     Json,
     Extension,
 };
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use mongodb::ClientSession;
 use chrono::Utc;
+use bcrypt::{hash, DEFAULT_COST};
 use validator::Validate;
 use futures::TryStreamExt;
+use serde::Deserialize;
 
 use crate::state::app_state::AppState;
 use crate::models::collection::{
@@ -15,9 +21,298 @@ use crate::models::collection::{
     CreateCollectionRequest,
     UpdateCollectionRequest,
     CollectionResponse,
+    MoveCollectionRequest,
+    ReorderCollectionRequest,
+    CollectionTreeNode,
+    CollectionShare,
+    CollectionKind,
+    ShareCollectionRequest,
+    ShareResponse,
+    MergeCollectionRequest,
 };
 use crate::models::bookmark::{Bookmark, BookmarkResponse};
+use crate::models::member::{CollectionMember, MemberRole};
+use crate::handlers::member::{require_role, shared_collections};
 use crate::utils::jwt::Claims;
+use crate::utils::filter::{collection_bookmark_filter, rule_filter};
+use crate::utils::ordering::{
+    SortOrder,
+    bookmark_sort,
+    collection_scope,
+    bookmark_scope,
+    bookmark_list_key,
+    bookmark_position_field,
+    append_position,
+    append_position_with_session,
+    key_between,
+    position_after,
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct GetCollectionQuery {
+    #[serde(default)]
+    pub include_descendants: bool,
+
+    pub sort: Option<SortOrder>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListCollectionsQuery {
+    // title sorts collections by name
+    pub sort: Option<SortOrder>,
+
+    // false hides archived collections, true lists only them
+    pub archived: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteMode {
+    // Bookmarks stay, without a collection
+    #[default]
+    Orphan,
+    // Bookmarks go to target_id
+    Move,
+    // Bookmarks are deleted too
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteCollectionQuery {
+    #[serde(default)]
+    pub mode: DeleteMode,
+
+    pub target_id: Option<String>,
+}
+
+
+// All collections of the user
+pub async fn user_collections(
+    state: &AppState,
+    user_id: ObjectId,
+) -> Result<Vec<Collection>, (StatusCode, String)> {
+
+    let collection = state.db.collection::<Collection>("collections");
+
+    let cursor = collection
+        .find(doc! { "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))
+}
+
+
+// Bookmark count of the given collections, in at most two aggregations.
+// Manual collections are grouped by collection_ids, which also covers
+// bookmarks added by other members of shared collections. Smart collections
+// count their rule's matches, one $facet branch each
+pub async fn bookmark_counts(
+    state: &AppState,
+    collections: &[Collection],
+) -> Result<HashMap<ObjectId, u64>, (StatusCode, String)> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let manual_ids: Vec<ObjectId> = collections
+        .iter()
+        .filter(|c| c.kind == CollectionKind::Manual)
+        .map(|c| c.id.unwrap())
+        .collect();
+
+    let mut counts: HashMap<ObjectId, u64> = HashMap::new();
+
+    if !manual_ids.is_empty() {
+        let pipeline = vec![
+            doc! { "$match": { "collection_ids": { "$in": &manual_ids } } },
+            doc! { "$unwind": "$collection_ids" },
+            doc! { "$match": { "collection_ids": { "$in": &manual_ids } } },
+            doc! { "$group": {
+                "_id": "$collection_ids",
+                "count": { "$sum": 1 }
+            }},
+        ];
+
+        for d in aggregate_counts(&bookmarks_col, pipeline).await? {
+            let id = d.get_object_id("_id")
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to count bookmarks".to_string()))?;
+            counts.insert(id, count_field(&d)?);
+        }
+    }
+
+    let smart: Vec<&Collection> = collections
+        .iter()
+        .filter(|c| c.kind == CollectionKind::Smart)
+        .collect();
+
+    if !smart.is_empty() {
+        let mut facets = Document::new();
+        for col in &smart {
+            facets.insert(
+                col.id.unwrap().to_hex(),
+                vec![
+                    doc! { "$match": collection_bookmark_filter(col)? },
+                    doc! { "$count": "count" },
+                ],
+            );
+        }
+
+        // Smart collections only match their owner's bookmarks
+        let mut owners: Vec<ObjectId> = smart.iter().map(|c| c.user_id).collect();
+        owners.sort();
+        owners.dedup();
+
+        let pipeline = vec![
+            doc! { "$match": { "user_id": { "$in": owners } } },
+            doc! { "$facet": facets },
+        ];
+
+        let results = aggregate_counts(&bookmarks_col, pipeline).await?;
+        let facets = results.first().cloned().unwrap_or_default();
+
+        for col in smart {
+            let id = col.id.unwrap();
+            // An empty branch means no match
+            let count = match facets.get_array(id.to_hex()).ok().and_then(|a| a.first()) {
+                Some(mongodb::bson::Bson::Document(d)) => count_field(d)?,
+                _ => 0,
+            };
+            counts.insert(id, count);
+        }
+    }
+
+    Ok(counts)
+}
+
+
+async fn aggregate_counts(
+    bookmarks_col: &mongodb::Collection<Bookmark>,
+    pipeline: Vec<Document>,
+) -> Result<Vec<Document>, (StatusCode, String)> {
+
+    let cursor = bookmarks_col
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to count bookmarks".to_string()))
+}
+
+
+// $sum and $count give an int32, or an int64 past its range
+pub fn count_field(d: &Document) -> Result<u64, (StatusCode, String)> {
+
+    match d.get("count") {
+        Some(mongodb::bson::Bson::Int32(n)) => Ok(*n as u64),
+        Some(mongodb::bson::Bson::Int64(n)) => Ok(*n as u64),
+        _ => Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to count bookmarks".to_string())),
+    }
+}
+
+
+// The collection and every collection below it
+pub fn descendant_ids(collections: &[Collection], root: ObjectId) -> Vec<ObjectId> {
+
+    let mut ids = vec![root];
+    let mut index = 0;
+
+    while index < ids.len() {
+        let current = ids[index];
+        for col in collections {
+            let id = col.id.unwrap();
+            if col.parent_id == Some(current) && !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        index += 1;
+    }
+
+    ids
+}
+
+
+// Full name path of every collection by id, e.g. ["Work", "Clients", "Acme"].
+// A path starts at the highest ancestor among the given collections
+pub fn collection_path_names(collections: &[Collection]) -> HashMap<ObjectId, Vec<String>> {
+
+    let by_id: HashMap<ObjectId, &Collection> = collections
+        .iter()
+        .map(|c| (c.id.unwrap(), c))
+        .collect();
+
+    let mut names = HashMap::new();
+
+    for col in collections {
+        let mut path = vec![col.name.clone()];
+        let mut parent = col.parent_id;
+
+        // Depth guard in case of corrupted parent links
+        while let Some(p) = parent.and_then(|id| by_id.get(&id)) {
+            if path.len() > 64 {
+                break;
+            }
+            path.push(p.name.clone());
+            parent = p.parent_id;
+        }
+
+        path.reverse();
+        names.insert(col.id.unwrap(), path);
+    }
+
+    names
+}
+
+
+// Collection id of every full name path, see collection_path_names.
+// Smart collections are left out since bookmarks can't be filed in them
+pub fn collection_paths(collections: &[Collection]) -> HashMap<Vec<String>, ObjectId> {
+
+    let mut names = collection_path_names(collections);
+
+    let mut paths = HashMap::new();
+
+    for col in collections.iter().filter(|c| c.kind == CollectionKind::Manual) {
+        let id = col.id.unwrap();
+        if let Some(path) = names.remove(&id) {
+            paths.entry(path).or_insert(id);
+        }
+    }
+
+    paths
+}
+
+
+// Make sure a parent collection exists and belongs to the user
+async fn check_parent(
+    state: &AppState,
+    user_id: ObjectId,
+    parent_id: &str,
+) -> Result<ObjectId, (StatusCode, String)> {
+
+    let parent_id = ObjectId::parse_str(parent_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid parent id".to_string()))?;
+
+    let collection = state.db.collection::<Collection>("collections");
+
+    let parent = collection
+        .find_one(doc! { "_id": parent_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Parent collection not found".to_string()))?;
+
+    if parent.kind == CollectionKind::Smart {
+        return Err((StatusCode::BAD_REQUEST, "Smart collections cannot have sub collections".to_string()));
+    }
+
+    Ok(parent_id)
+}
 
 
 // Create collection
@@ -34,6 +329,24 @@ pub async fn create_collection(
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
+    let parent_id = match &body.parent_id {
+        Some(id) => Some(check_parent(&state, user_id, id).await?),
+        None => None,
+    };
+
+    // Reject rules that don't compile before saving them
+    if let Some(rule) = &body.rule {
+        rule_filter(rule)?;
+    }
+
+    let kind = if body.rule.is_some() { CollectionKind::Smart } else { CollectionKind::Manual };
+
+    // New collections go last among their siblings
+    let position = append_position(
+        &state.db.collection::<Document>("collections"),
+        &collection_scope(user_id, parent_id),
+    ).await?;
+
     let now = Utc::now();
 
     let collection_doc = Collection {
@@ -41,6 +354,16 @@ pub async fn create_collection(
         user_id,
         name: body.name,
         description: body.description,
+        icon: body.icon.filter(|i| !i.is_empty()),
+        color: body.color.filter(|c| !c.is_empty()).map(|c| c.to_lowercase()),
+        cover_url: body.cover_url.filter(|u| !u.is_empty()),
+        pinned: body.pinned,
+        archived: false,
+        parent_id,
+        kind,
+        position: Some(position),
+        rule: body.rule,
+        share: None,
         created_at: now,
         updated_at: now,
     };
@@ -52,70 +375,75 @@ pub async fn create_collection(
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create collection".to_string()))?;
 
-    let response = CollectionResponse {
-        id: result.inserted_id.as_object_id().unwrap().to_hex(),
-        name: collection_doc.name,
-        description: collection_doc.description,
-        bookmark_count: 0,
-        created_at: collection_doc.created_at,
-        updated_at: collection_doc.updated_at,
-    };
+    let mut created = collection_doc;
+    created.id = Some(result.inserted_id.as_object_id().unwrap());
 
-    Ok((StatusCode::CREATED, Json(response)))
+    let counts = bookmark_counts(&state, std::slice::from_ref(&created)).await?;
+    let count = counts.get(&created.id.unwrap()).copied().unwrap_or(0);
+
+    Ok((StatusCode::CREATED, Json(CollectionResponse::new(created, count, MemberRole::Owner))))
 }
 
 
-// List all collections for user
+// List the user's collections and the ones shared with them
 pub async fn list_collections(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
+    Query(query): Query<ListCollectionsQuery>,
 ) -> Result<Json<Vec<CollectionResponse>>, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
-    let collection = state.db.collection::<Collection>("collections");
-    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+    let mut collections = user_collections(&state, user_id).await?;
 
-    let cursor = collection
-        .find(doc! { "user_id": user_id }, None)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+    let mut roles: HashMap<ObjectId, MemberRole> = collections
+        .iter()
+        .map(|c| (c.id.unwrap(), MemberRole::Owner))
+        .collect();
 
-    let collections: Vec<Collection> = cursor
-        .try_collect()
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+    // Shared collections come with everything below them
+    for (id, (col, role)) in shared_collections(&state, user_id).await? {
+        if roles.insert(id, role).is_none() {
+            collections.push(col);
+        }
+    }
 
-    let mut response: Vec<CollectionResponse> = Vec::new();
+    if let Some(archived) = query.archived {
+        collections.retain(|c| c.archived == archived);
+    }
 
-    for col in collections {
-        let col_id = col.id.unwrap();
+    match query.sort {
+        Some(SortOrder::Manual) => collections.sort_by_key(manual_order),
+        Some(SortOrder::Title) => collections.sort_by_key(|c| c.name.to_lowercase()),
+        Some(SortOrder::CreatedAt) => collections.sort_by_key(|c| std::cmp::Reverse(c.created_at)),
+        None => {}
+    }
 
-        let count = bookmarks_col
-            .count_documents(doc! { "collection_id": col_id, "user_id": user_id }, None)
-            .await
-            .unwrap_or(0);
+    // Pinned collections come first, the sort is stable so each group keeps its order
+    collections.sort_by_key(|c| !c.pinned);
 
-        response.push(CollectionResponse {
-            id: col_id.to_hex(),
-            name: col.name,
-            description: col.description,
-            bookmark_count: count,
-            created_at: col.created_at,
-            updated_at: col.updated_at,
-        });
-    }
+    let counts = bookmark_counts(&state, &collections).await?;
+
+    let response: Vec<CollectionResponse> = collections
+        .into_iter()
+        .map(|col| {
+            let col_id = col.id.unwrap();
+            let count = counts.get(&col_id).copied().unwrap_or(0);
+            CollectionResponse::new(col, count, roles[&col_id])
+        })
+        .collect();
 
     Ok(Json(response))
 }
 
 
-// Get collection with its bookmarks
+// Get collection with its bookmarks, optionally including sub collections
 pub async fn get_collection(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Path(id): Path<String>,
+    Query(query): Query<GetCollectionQuery>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
@@ -124,19 +452,29 @@ pub async fn get_collection(
     let collection_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
 
-    let collection = state.db.collection::<Collection>("collections");
     let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
 
-    // Get collection
-    let col = collection
-        .find_one(doc! { "_id": collection_id, "user_id": user_id }, None)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+    // Get collection, owned or shared with the user
+    let (col, role) = require_role(&state, user_id, collection_id, MemberRole::Viewer).await?;
+
+    // Smart collections are evaluated now, manual ones hold
+    // the bookmarks filed in them, whoever added them
+    let filter = if col.kind == CollectionKind::Smart {
+        collection_bookmark_filter(&col)?
+    } else if query.include_descendants {
+        let collection_ids = descendant_ids(&user_collections(&state, col.user_id).await?, collection_id);
+        doc! { "collection_ids": { "$in": collection_ids } }
+    } else {
+        doc! { "collection_ids": collection_id }
+    };
+
+    let options = FindOptions::builder()
+        .sort(query.sort.map(|sort| bookmark_sort(sort, Some(collection_id))))
+        .build();
 
     // Get bookmarks in collection
     let cursor = bookmarks_col
-        .find(doc! { "collection_id": collection_id, "user_id": user_id }, None)
+        .find(filter, options)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
 
@@ -154,8 +492,18 @@ pub async fn get_collection(
         "id": col.id.unwrap().to_hex(),
         "name": col.name,
         "description": col.description,
+        "icon": col.icon,
+        "color": col.color,
+        "cover_url": col.cover_url,
+        "pinned": col.pinned,
+        "archived": col.archived,
+        "parent_id": col.parent_id.map(|id| id.to_hex()),
+        "kind": col.kind,
+        "rule": col.rule,
+        "position": col.position,
         "bookmarks": bookmark_responses,
         "bookmark_count": bookmark_responses.len(),
+        "role": role,
         "created_at": col.created_at,
         "updated_at": col.updated_at
     });
@@ -172,6 +520,10 @@ pub async fn update_collection(
     Json(body): Json<UpdateCollectionRequest>,
 ) -> Result<Json<CollectionResponse>, (StatusCode, String)> {
 
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
@@ -179,7 +531,6 @@ pub async fn update_collection(
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
 
     let collection = state.db.collection::<Collection>("collections");
-    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
 
     let mut update_doc = doc! {
         "updated_at": Utc::now().to_rfc3339()
@@ -191,13 +542,52 @@ pub async fn update_collection(
     if let Some(description) = &body.description {
         update_doc.insert("description", description);
     }
+    if let Some(pinned) = body.pinned {
+        update_doc.insert("pinned", pinned);
+    }
+    if let Some(archived) = body.archived {
+        update_doc.insert("archived", archived);
+    }
+
+    // Empty strings remove the field
+    let mut unset_doc = Document::new();
+
+    for (field, value) in [
+        ("icon", body.icon.clone()),
+        ("color", body.color.as_ref().map(|c| c.to_lowercase())),
+        ("cover_url", body.cover_url.clone()),
+    ] {
+        match value {
+            Some(value) if value.is_empty() => { unset_doc.insert(field, ""); }
+            Some(value) => { update_doc.insert(field, value); }
+            None => {}
+        }
+    }
+    if let Some(rule) = &body.rule {
+        let existing = collection
+            .find_one(doc! { "_id": collection_id, "user_id": user_id }, None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+            .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+        if existing.kind != CollectionKind::Smart {
+            return Err((StatusCode::BAD_REQUEST, "Only smart collections have a rule".to_string()));
+        }
+
+        rule_filter(rule)?;
+
+        let rule = mongodb::bson::to_bson(rule)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update collection".to_string()))?;
+        update_doc.insert("rule", rule);
+    }
+
+    let mut update = doc! { "$set": update_doc };
+    if !unset_doc.is_empty() {
+        update.insert("$unset", unset_doc);
+    }
 
     collection
-        .update_one(
-            doc! { "_id": collection_id, "user_id": user_id },
-            doc! { "$set": update_doc },
-            None,
-        )
+        .update_one(doc! { "_id": collection_id, "user_id": user_id }, update, None)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update collection".to_string()))?;
 
@@ -207,29 +597,20 @@ pub async fn update_collection(
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
         .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
 
-    let count = bookmarks_col
-        .count_documents(doc! { "collection_id": collection_id, "user_id": user_id }, None)
-        .await
-        .unwrap_or(0);
+    let counts = bookmark_counts(&state, std::slice::from_ref(&col)).await?;
 
-    let response = CollectionResponse {
-        id: col.id.unwrap().to_hex(),
-        name: col.name,
-        description: col.description,
-        bookmark_count: count,
-        created_at: col.created_at,
-        updated_at: col.updated_at,
-    };
+    let count = counts.get(&collection_id).copied().unwrap_or(0);
 
-    Ok(Json(response))
+    Ok(Json(CollectionResponse::new(col, count, MemberRole::Owner)))
 }
 
 
-// Delete collection
+// Delete collection, its bookmarks are kept, moved or deleted depending on the mode
 pub async fn delete_collection(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Path(id): Path<String>,
+    Query(query): Query<DeleteCollectionQuery>,
 ) -> Result<StatusCode, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
@@ -238,28 +619,851 @@ pub async fn delete_collection(
     let collection_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
 
+    let target_id = match (query.mode, &query.target_id) {
+        (DeleteMode::Move, Some(target_id)) => {
+            Some(check_target(&state, user_id, target_id, collection_id, MemberRole::Editor).await?.id.unwrap())
+        }
+        (DeleteMode::Move, None) => {
+            return Err((StatusCode::BAD_REQUEST, "target_id required to move bookmarks".to_string()));
+        }
+        _ => None,
+    };
+
+    let mut session = start_session(&state).await?;
+
+    let result = delete_writes(&state, &mut session, user_id, collection_id, query.mode, target_id).await;
+
+    end_session(&state, &mut session, result).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+async fn delete_writes(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    collection_id: ObjectId,
+    mode: DeleteMode,
+    target_id: Option<ObjectId>,
+) -> Result<(), (StatusCode, String)> {
+
     let collection = state.db.collection::<Collection>("collections");
     let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
 
-    // Delete collection
-    let result = collection
-        .delete_one(doc! { "_id": collection_id, "user_id": user_id }, None)
+    // The collection itself goes last, so without a transaction a delete that
+    // fails part way leaves it in place to be deleted again
+    let deleted = collection
+        .find_one_with_session(doc! { "_id": collection_id, "user_id": user_id }, None, session)
         .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete collection".to_string()))?;
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
 
-    if result.deleted_count == 0 {
-        return Err((StatusCode::NOT_FOUND, "Collection not found".to_string()));
+    match (mode, target_id) {
+        (DeleteMode::Move, Some(target_id)) => {
+            move_bookmarks(state, session, collection_id, target_id).await?;
+        }
+        (DeleteMode::Delete, _) => {
+            // Only the owner's bookmarks are deleted, and only when in no other
+            // collection. Bookmarks of members are unfiled below like in orphan mode
+            bookmarks_col
+                .delete_many_with_session(doc! { "collection_ids": [collection_id], "user_id": user_id }, None, session)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete bookmarks".to_string()))?;
+        }
+        _ => {}
     }
 
-    // Remove collection_id from bookmarks (don't delete bookmarks)
+    // Only the membership goes, bookmarks left in no collection become unfiled
     bookmarks_col
-        .update_many(
-            doc! { "collection_id": collection_id, "user_id": user_id },
-            doc! { "$unset": { "collection_id": "" } },
+        .update_many_with_session(
+            doc! { "collection_ids": collection_id },
+            doc! {
+                "$pull": { "collection_ids": collection_id },
+                "$unset": { bookmark_position_field(Some(collection_id)): "" }
+            },
             None,
+            session,
         )
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update bookmarks".to_string()))?;
 
-    Ok(StatusCode::NO_CONTENT)
-}
\ No newline at end of file
+    state.db.collection::<CollectionMember>("collection_members")
+        .delete_many_with_session(doc! { "collection_id": collection_id }, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove members".to_string()))?;
+
+    // Sub collections move up to the deleted collection's parent
+    let reparent = match deleted.parent_id {
+        Some(parent_id) => doc! { "$set": { "parent_id": parent_id } },
+        None => doc! { "$unset": { "parent_id": "" } },
+    };
+
+    collection
+        .update_many_with_session(doc! { "parent_id": collection_id, "user_id": user_id }, reparent, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update sub collections".to_string()))?;
+
+    collection
+        .delete_one_with_session(doc! { "_id": collection_id, "user_id": user_id }, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete collection".to_string()))?;
+
+    Ok(())
+}
+
+
+// Move a collection's bookmarks and sub collections into another one, then delete it
+pub async fn merge_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<MergeCollectionRequest>,
+) -> Result<Json<CollectionResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let collections = user_collections(&state, user_id).await?;
+
+    let source = collections
+        .iter()
+        .find(|c| c.id == Some(collection_id))
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    if source.kind == CollectionKind::Smart {
+        return Err((StatusCode::BAD_REQUEST, "Smart collections cannot be merged".to_string()));
+    }
+
+    // Sub collections move along, so the target must belong to the user too
+    let target = check_target(&state, user_id, &body.target_id, collection_id, MemberRole::Owner).await?;
+    let target_id = target.id.unwrap();
+
+    if descendant_ids(&collections, collection_id).contains(&target_id) {
+        return Err((StatusCode::BAD_REQUEST, "Cannot merge a collection into its descendants".to_string()));
+    }
+
+    let mut session = start_session(&state).await?;
+
+    let result = merge_writes(&state, &mut session, user_id, collection_id, target_id).await;
+
+    end_session(&state, &mut session, result).await?;
+
+    let col = state.db.collection::<Collection>("collections")
+        .find_one(doc! { "_id": target_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    let counts = bookmark_counts(&state, std::slice::from_ref(&col)).await?;
+    let count = counts.get(&target_id).copied().unwrap_or(0);
+
+    Ok(Json(CollectionResponse::new(col, count, MemberRole::Owner)))
+}
+
+
+async fn merge_writes(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    collection_id: ObjectId,
+    target_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+
+    let collection = state.db.collection::<Collection>("collections");
+    let docs_col = state.db.collection::<Document>("collections");
+
+    move_bookmarks(state, session, collection_id, target_id).await?;
+
+    // Sub collections go last under the target, in their current order
+    let mut cursor = collection
+        .find_with_session(
+            doc! { "parent_id": collection_id, "user_id": user_id },
+            FindOptions::builder().sort(doc! { "position": 1, "name": 1, "_id": 1 }).build(),
+            session,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let children: Vec<Collection> = cursor
+        .stream(session)
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+
+    let mut position: Option<String> = None;
+
+    for child in children {
+        let key = match &position {
+            Some(previous) => key_between(Some(previous), None),
+            None => append_position_with_session(&docs_col, &collection_scope(user_id, Some(target_id)), session)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+        };
+
+        collection
+            .update_one_with_session(
+                doc! { "_id": child.id.unwrap() },
+                doc! { "$set": { "parent_id": target_id, "position": &key, "updated_at": Utc::now().to_rfc3339() } },
+                None,
+                session,
+            )
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update sub collections".to_string()))?;
+
+        position = Some(key);
+    }
+
+    state.db.collection::<CollectionMember>("collection_members")
+        .delete_many_with_session(doc! { "collection_id": collection_id }, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove members".to_string()))?;
+
+    // The source goes last, so without a transaction a merge that fails part
+    // way leaves it in place to be merged again
+    collection
+        .delete_one_with_session(doc! { "_id": collection_id, "user_id": user_id }, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete collection".to_string()))?;
+
+    collection
+        .update_one_with_session(
+            doc! { "_id": target_id },
+            doc! { "$set": { "updated_at": Utc::now().to_rfc3339() } },
+            None,
+            session,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update collection".to_string()))?;
+
+    Ok(())
+}
+
+
+// Copy a collection and the user's own bookmarks in it, sub collections are not
+// copied. A collection shared with the user is copied into their own top level,
+// bookmarks other members added stay theirs and are left out
+pub async fn duplicate_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<CollectionResponse>), (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let (source, role) = require_role(&state, user_id, collection_id, MemberRole::Viewer).await?;
+
+    let mut session = start_session(&state).await?;
+
+    let result = duplicate_writes(&state, &mut session, user_id, source, role).await;
+
+    let copy = end_session(&state, &mut session, result).await?;
+
+    let counts = bookmark_counts(&state, std::slice::from_ref(&copy)).await?;
+    let count = counts.get(&copy.id.unwrap()).copied().unwrap_or(0);
+
+    Ok((StatusCode::CREATED, Json(CollectionResponse::new(copy, count, MemberRole::Owner))))
+}
+
+
+async fn duplicate_writes(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    source: Collection,
+    role: MemberRole,
+) -> Result<Collection, (StatusCode, String)> {
+
+    let collection = state.db.collection::<Collection>("collections");
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let parent_id = if role == MemberRole::Owner { source.parent_id } else { None };
+
+    let position = append_position_with_session(
+        &state.db.collection::<Document>("collections"),
+        &collection_scope(user_id, parent_id),
+        session,
+    )
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let now = Utc::now();
+
+    let mut copy = Collection {
+        id: None,
+        user_id,
+        name: format!("{} (copy)", source.name),
+        description: source.description,
+        icon: source.icon,
+        color: source.color,
+        cover_url: source.cover_url,
+        pinned: false,
+        archived: false,
+        parent_id,
+        kind: source.kind,
+        position: Some(position),
+        rule: source.rule,
+        share: None,
+        created_at: now,
+        updated_at: now,
+    };
+
+    let result = collection
+        .insert_one_with_session(&copy, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create collection".to_string()))?;
+
+    let copy_id = result.inserted_id.as_object_id().unwrap();
+    copy.id = Some(copy_id);
+
+    // Smart collections hold no bookmarks, the copied rule is enough
+    if source.kind == CollectionKind::Smart {
+        return Ok(copy);
+    }
+
+    let copied = copy_bookmarks(state, session, user_id, source.id.unwrap(), copy_id).await;
+
+    // Without a transaction a half made copy is removed again
+    if copied.is_err() && !state.supports_transactions {
+        let _ = bookmarks_col
+            .delete_many(doc! { "collection_ids": copy_id, "user_id": user_id }, None)
+            .await;
+        let _ = collection
+            .delete_one(doc! { "_id": copy_id, "user_id": user_id }, None)
+            .await;
+    }
+
+    copied.map(|_| copy)
+}
+
+
+async fn copy_bookmarks(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    source_id: ObjectId,
+    copy_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let mut cursor = bookmarks_col
+        .find_with_session(doc! { "collection_ids": source_id, "user_id": user_id }, None, session)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let bookmarks: Vec<Bookmark> = cursor
+        .stream(session)
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bookmarks".to_string()))?;
+
+    // Copies keep their manual order and dates, and are only in the copy
+    let now = Utc::now();
+    let source_key = bookmark_list_key(Some(source_id));
+    let copies: Vec<Bookmark> = bookmarks
+        .into_iter()
+        .map(|mut b| {
+            let positions = b.positions
+                .remove(&source_key)
+                .map(|position| HashMap::from([(bookmark_list_key(Some(copy_id)), position)]))
+                .unwrap_or_default();
+
+            Bookmark {
+                id: None,
+                user_id,
+                collection_ids: vec![copy_id],
+                positions,
+                updated_at: now,
+                ..b
+            }
+        })
+        .collect();
+
+    if !copies.is_empty() {
+        bookmarks_col
+            .insert_many_with_session(&copies, None, session)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to copy bookmarks".to_string()))?;
+    }
+
+    Ok(())
+}
+
+
+// Manual collection the bookmarks of another one can be moved into
+async fn check_target(
+    state: &AppState,
+    user_id: ObjectId,
+    target_id: &str,
+    collection_id: ObjectId,
+    min_role: MemberRole,
+) -> Result<Collection, (StatusCode, String)> {
+
+    let target_id = ObjectId::parse_str(target_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid target id".to_string()))?;
+
+    if target_id == collection_id {
+        return Err((StatusCode::BAD_REQUEST, "Target must be another collection".to_string()));
+    }
+
+    let (target, _) = require_role(state, user_id, target_id, min_role).await?;
+
+    if target.kind == CollectionKind::Smart {
+        return Err((StatusCode::BAD_REQUEST, "Bookmarks cannot be added to a smart collection".to_string()));
+    }
+
+    Ok(target)
+}
+
+
+// Move every bookmark of a collection to the end of another one, keeping
+// their order. Bookmarks already in both just leave the first
+async fn move_bookmarks(
+    state: &AppState,
+    session: &mut ClientSession,
+    from: ObjectId,
+    to: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+    let docs_col = state.db.collection::<Document>("bookmarks");
+
+    let mut cursor = bookmarks_col
+        .find_with_session(
+            doc! { "collection_ids": from },
+            FindOptions::builder()
+                .sort(doc! { bookmark_position_field(Some(from)): 1, "created_at": 1, "_id": 1 })
+                .build(),
+            session,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let bookmarks: Vec<Bookmark> = cursor
+        .stream(session)
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bookmarks".to_string()))?;
+
+    let from_key = bookmark_list_key(Some(from));
+    let to_key = bookmark_list_key(Some(to));
+    let mut position: Option<String> = None;
+
+    for mut bookmark in bookmarks {
+        bookmark.collection_ids.retain(|id| *id != from);
+        bookmark.positions.remove(&from_key);
+
+        if !bookmark.collection_ids.contains(&to) {
+            let key = match &position {
+                Some(previous) => key_between(Some(previous), None),
+                None => append_position_with_session(&docs_col, &bookmark_scope(bookmark.user_id, Some(to)), session)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+            };
+
+            bookmark.collection_ids.push(to);
+            bookmark.positions.insert(to_key.clone(), key.clone());
+            position = Some(key);
+        }
+
+        let positions = mongodb::bson::to_document(&bookmark.positions)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to move bookmarks".to_string()))?;
+
+        bookmarks_col
+            .update_one_with_session(
+                doc! { "_id": bookmark.id.unwrap() },
+                doc! { "$set": {
+                    "collection_ids": &bookmark.collection_ids,
+                    "positions": positions,
+                    "updated_at": Utc::now().to_rfc3339()
+                } },
+                None,
+                session,
+            )
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to move bookmarks".to_string()))?;
+    }
+
+    Ok(())
+}
+
+
+// Session for a change spanning several writes, inside a transaction
+// when the server supports one so they apply all together or not at all
+async fn start_session(state: &AppState) -> Result<ClientSession, (StatusCode, String)> {
+
+    let mut session = state.db
+        .collection::<Collection>("collections")
+        .client()
+        .start_session(None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start session".to_string()))?;
+
+    if state.supports_transactions {
+        session
+            .start_transaction(None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start transaction".to_string()))?;
+    }
+
+    Ok(session)
+}
+
+
+// Commit the writes of start_session when they all succeeded, roll them back otherwise
+async fn end_session<T>(
+    state: &AppState,
+    session: &mut ClientSession,
+    result: Result<T, (StatusCode, String)>,
+) -> Result<T, (StatusCode, String)> {
+
+    if !state.supports_transactions {
+        return result;
+    }
+
+    if result.is_ok() {
+        session
+            .commit_transaction()
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to commit changes".to_string()))?;
+    } else {
+        let _ = session.abort_transaction().await;
+    }
+
+    result
+}
+
+
+// Move a collection and its subtree under another parent, or to the top level
+pub async fn move_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<MoveCollectionRequest>,
+) -> Result<Json<CollectionResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let collection = state.db.collection::<Collection>("collections");
+
+    let collections = user_collections(&state, user_id).await?;
+
+    if !collections.iter().any(|c| c.id == Some(collection_id)) {
+        return Err((StatusCode::NOT_FOUND, "Collection not found".to_string()));
+    }
+
+    let parent_id = match &body.parent_id {
+        Some(parent_id) => {
+            let parent_id = check_parent(&state, user_id, parent_id).await?;
+
+            // A collection can't end up inside its own subtree
+            if descendant_ids(&collections, collection_id).contains(&parent_id) {
+                return Err((StatusCode::BAD_REQUEST, "Cannot move a collection into itself or its descendants".to_string()));
+            }
+
+            Some(parent_id)
+        }
+        None => None,
+    };
+
+    let docs_col = state.db.collection::<Document>("collections");
+    let scope = collection_scope(user_id, parent_id);
+
+    let position = match parse_after_id(body.after_id.as_deref(), collection_id)? {
+        Some(after_id) => position_after(&docs_col, &scope, "name", collection_id, Some(after_id)).await?,
+        None => append_position(&docs_col, &scope).await?,
+    };
+
+    let update_doc = match parent_id {
+        Some(parent_id) => doc! {
+            "$set": { "parent_id": parent_id, "position": position, "updated_at": Utc::now().to_rfc3339() }
+        },
+        None => doc! {
+            "$set": { "position": position, "updated_at": Utc::now().to_rfc3339() },
+            "$unset": { "parent_id": "" }
+        },
+    };
+
+    collection
+        .update_one(doc! { "_id": collection_id, "user_id": user_id }, update_doc, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to move collection".to_string()))?;
+
+    let col = collection
+        .find_one(doc! { "_id": collection_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    let counts = bookmark_counts(&state, std::slice::from_ref(&col)).await?;
+
+    let count = counts.get(&collection_id).copied().unwrap_or(0);
+
+    Ok(Json(CollectionResponse::new(col, count, MemberRole::Owner)))
+}
+
+
+// Place a collection after one of its siblings
+pub async fn reorder_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<ReorderCollectionRequest>,
+) -> Result<Json<CollectionResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let collection = state.db.collection::<Collection>("collections");
+
+    let col = collection
+        .find_one(doc! { "_id": collection_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    let position = position_after(
+        &state.db.collection::<Document>("collections"),
+        &collection_scope(user_id, col.parent_id),
+        "name",
+        collection_id,
+        parse_after_id(body.after_id.as_deref(), collection_id)?,
+    ).await?;
+
+    collection
+        .update_one(
+            doc! { "_id": collection_id },
+            doc! { "$set": { "position": &position, "updated_at": Utc::now().to_rfc3339() } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to reorder collection".to_string()))?;
+
+    let counts = bookmark_counts(&state, std::slice::from_ref(&col)).await?;
+
+    let col = Collection { position: Some(position), ..col };
+    let count = counts.get(&collection_id).copied().unwrap_or(0);
+
+    Ok(Json(CollectionResponse::new(col, count, MemberRole::Owner)))
+}
+
+
+fn parse_after_id(after_id: Option<&str>, collection_id: ObjectId) -> Result<Option<ObjectId>, (StatusCode, String)> {
+
+    let Some(after_id) = after_id else {
+        return Ok(None);
+    };
+
+    let after_id = ObjectId::parse_str(after_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid after_id".to_string()))?;
+
+    if after_id == collection_id {
+        return Err((StatusCode::BAD_REQUEST, "A collection cannot be placed after itself".to_string()));
+    }
+
+    Ok(Some(after_id))
+}
+
+
+// Sidebar order, collections never reordered keep their name order
+fn manual_order(col: &Collection) -> (Option<String>, String, Option<ObjectId>) {
+    (col.position.clone(), col.name.to_lowercase(), col.id)
+}
+
+
+// Full collection hierarchy with recursive bookmark counts
+pub async fn collection_tree(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<CollectionTreeNode>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collections = user_collections(&state, user_id).await?;
+
+    let ids: Vec<ObjectId> = collections.iter().map(|c| c.id.unwrap()).collect();
+
+    let counts = bookmark_counts(&state, &collections).await?;
+
+    // Collections whose parent no longer exists are shown at the top level
+    let mut by_parent: HashMap<Option<ObjectId>, Vec<Collection>> = HashMap::new();
+    for col in collections {
+        let parent = col.parent_id.filter(|p| ids.contains(p));
+        by_parent.entry(parent).or_default().push(col);
+    }
+
+    Ok(Json(build_tree(None, &mut by_parent, &counts)))
+}
+
+
+fn build_tree(
+    parent: Option<ObjectId>,
+    by_parent: &mut HashMap<Option<ObjectId>, Vec<Collection>>,
+    counts: &HashMap<ObjectId, u64>,
+) -> Vec<CollectionTreeNode> {
+
+    let mut children = by_parent.remove(&parent).unwrap_or_default();
+    children.sort_by_key(manual_order);
+
+    children
+        .into_iter()
+        .map(|col| {
+            let id = col.id.unwrap();
+            let nodes = build_tree(Some(id), by_parent, counts);
+            let count = counts.get(&id).copied().unwrap_or(0);
+
+            CollectionTreeNode {
+                id: id.to_hex(),
+                name: col.name,
+                description: col.description,
+                icon: col.icon,
+                color: col.color,
+                pinned: col.pinned,
+                archived: col.archived,
+                parent_id: col.parent_id.map(|p| p.to_hex()),
+                kind: col.kind,
+                bookmark_count: count,
+                total_bookmark_count: count + nodes.iter().map(|n| n.total_bookmark_count).sum::<u64>(),
+                children: nodes,
+                created_at: col.created_at,
+                updated_at: col.updated_at,
+            }
+        })
+        .collect()
+}
+
+
+// Current public link of a collection
+pub async fn get_share(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<ShareResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let (col, _) = require_role(&state, user_id, collection_id, MemberRole::Owner).await?;
+
+    let share = col.share
+        .ok_or((StatusCode::NOT_FOUND, "Collection is not shared".to_string()))?;
+
+    Ok(Json(ShareResponse::from(share)))
+}
+
+
+// Publish a collection, or change the password and expiry of its link
+pub async fn share_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<ShareCollectionRequest>,
+) -> Result<Json<ShareResponse>, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let (col, _) = require_role(&state, user_id, collection_id, MemberRole::Owner).await?;
+
+    if body.expires_at.is_some_and(|e| e <= Utc::now()) {
+        return Err((StatusCode::BAD_REQUEST, "Expiry must be in the future".to_string()));
+    }
+
+    // A new password replaces the old one, "" or remove_password takes it off,
+    // otherwise the link stays as protected as it was
+    let password_hash = match body.password.as_deref() {
+        _ if body.remove_password => None,
+        Some("") => None,
+        Some(password) => {
+            let password = password.to_string();
+            let hashed = tokio::task::spawn_blocking(move || hash(password, DEFAULT_COST))
+                .await
+                .ok()
+                .and_then(|r| r.ok())
+                .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password".to_string()))?;
+            Some(hashed)
+        }
+        None => col.share.as_ref().and_then(|s| s.password_hash.clone()),
+    };
+
+    // Keep the existing link unless a new one is asked for
+    let existing = col.share.filter(|_| !body.regenerate);
+
+    let share = CollectionShare {
+        // 122 random bits, not guessable
+        slug: existing.as_ref()
+            .map(|s| s.slug.clone())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+        password_hash,
+        expires_at: body.expires_at,
+        created_at: existing.map(|s| s.created_at).unwrap_or_else(Utc::now),
+    };
+
+    let share_doc = mongodb::bson::to_bson(&share)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to share collection".to_string()))?;
+
+    state.db.collection::<Collection>("collections")
+        .update_one(
+            doc! { "_id": collection_id },
+            doc! { "$set": { "share": share_doc, "updated_at": Utc::now().to_rfc3339() } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to share collection".to_string()))?;
+
+    Ok(Json(ShareResponse::from(share)))
+}
+
+
+// Turn off the public link
+pub async fn unshare_collection(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    require_role(&state, user_id, collection_id, MemberRole::Owner).await?;
+
+    state.db.collection::<Collection>("collections")
+        .update_one(
+            doc! { "_id": collection_id },
+            doc! { "$unset": { "share": "" }, "$set": { "updated_at": Utc::now().to_rfc3339() } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to unshare collection".to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
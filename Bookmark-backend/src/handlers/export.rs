@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{State, Query},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::Cursor;
+use mongodb::options::AggregateOptions;
+use futures::{stream, TryStreamExt};
+use serde::Deserialize;
+
+use crate::state::app_state::AppState;
+use crate::models::bookmark::Bookmark;
+use crate::handlers::collection::{user_collections, collection_path_names};
+use crate::handlers::member::shared_collections;
+use crate::utils::jwt::Claims;
+use crate::utils::filter::{BookmarkFilter, bookmark_filter};
+use crate::utils::escape;
+
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Markdown,
+    Opml,
+    Urls,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+
+    // Comma separated CSV columns
+    pub columns: Option<String>,
+
+    #[serde(flatten)]
+    pub filter: BookmarkFilter,
+}
+
+
+const CSV_COLUMNS: [&str; 10] = [
+    "id",
+    "title",
+    "url",
+    "description",
+    "tags",
+    "keyword",
+    "collection",
+    "collection_id",
+    "created_at",
+    "updated_at",
+];
+
+const DEFAULT_CSV_COLUMNS: [&str; 6] = ["title", "url", "description", "tags", "collection", "created_at"];
+
+
+// Renders one export format piece by piece as the cursor is read
+struct Renderer {
+    format: ExportFormat,
+    columns: Vec<String>,
+    collection_names: HashMap<ObjectId, String>,
+    // Collection of the group being written, None for unfiled bookmarks
+    current_group: Option<Option<ObjectId>>,
+}
+
+impl Renderer {
+
+    fn header(&self) -> String {
+        match self.format {
+            ExportFormat::Csv => format!("{}\n", self.columns.join(",")),
+            ExportFormat::Markdown => "# Bookmarks\n".to_string(),
+            ExportFormat::Opml => concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<opml version=\"2.0\">\n",
+                "<head><title>Bookmarks</title></head>\n",
+                "<body>\n",
+            ).to_string(),
+            ExportFormat::Urls => String::new(),
+        }
+    }
+
+    fn footer(&self) -> String {
+        match self.format {
+            ExportFormat::Opml => {
+                let close = if self.current_group.is_some() { "</outline>\n" } else { "" };
+                format!("{}</body>\n</opml>\n", close)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn group_name(&self, collection_id: Option<ObjectId>) -> String {
+        collection_id
+            .and_then(|id| self.collection_names.get(&id).cloned())
+            .unwrap_or_else(|| "Unfiled".to_string())
+    }
+
+    fn bookmark(&mut self, b: &Bookmark) -> String {
+
+        let mut out = String::new();
+
+        let grouped = matches!(self.format, ExportFormat::Markdown | ExportFormat::Opml);
+
+        // Bookmarks arrive sorted by collection, once per collection they are in,
+        // start a new group when it changes
+        let group = b.collection_ids.first().copied();
+
+        if grouped && self.current_group != Some(group) {
+            let name = self.group_name(group);
+
+            match self.format {
+                ExportFormat::Markdown => out.push_str(&format!("\n## {}\n\n", escape::markdown(&name))),
+                _ => {
+                    if self.current_group.is_some() {
+                        out.push_str("</outline>\n");
+                    }
+                    out.push_str(&format!("<outline text=\"{}\">\n", escape::xml(&name)));
+                }
+            }
+
+            self.current_group = Some(group);
+        }
+
+        match self.format {
+            ExportFormat::Csv => {
+                let fields: Vec<String> = self.columns
+                    .iter()
+                    .map(|c| escape::csv(&self.csv_field(b, c)))
+                    .collect();
+                out.push_str(&fields.join(","));
+                out.push('\n');
+            }
+            ExportFormat::Markdown => {
+                out.push_str(&format!("- [{}]({})", escape::markdown(&b.title), b.url.replace(')', "%29")));
+                if let Some(description) = b.description.as_deref().filter(|d| !d.is_empty()) {
+                    out.push_str(&format!(" - {}", escape::markdown(description)));
+                }
+                if !b.tags.is_empty() {
+                    let tags: Vec<String> = b.tags.iter().map(|t| format!("`{}`", t)).collect();
+                    out.push_str(&format!(" {}", tags.join(" ")));
+                }
+                out.push('\n');
+            }
+            ExportFormat::Opml => {
+                out.push_str(&format!(
+                    "<outline type=\"link\" text=\"{}\" url=\"{}\"",
+                    escape::xml(&b.title),
+                    escape::xml(&b.url),
+                ));
+                if let Some(description) = &b.description {
+                    out.push_str(&format!(" description=\"{}\"", escape::xml(description)));
+                }
+                if !b.tags.is_empty() {
+                    out.push_str(&format!(" category=\"{}\"", escape::xml(&b.tags.join(","))));
+                }
+                out.push_str(&format!(" created=\"{}\"/>\n", b.created_at.to_rfc2822()));
+            }
+            ExportFormat::Urls => {
+                out.push_str(&b.url);
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    fn csv_field(&self, b: &Bookmark, column: &str) -> String {
+        match column {
+            "id" => b.id.map(|id| id.to_hex()).unwrap_or_default(),
+            "title" => b.title.clone(),
+            "url" => b.url.clone(),
+            "description" => b.description.clone().unwrap_or_default(),
+            "tags" => b.tags.join(";"),
+            "keyword" => b.keyword.clone().unwrap_or_default(),
+            "collection" => b.collection_ids
+                .iter()
+                .filter_map(|id| self.collection_names.get(id).cloned())
+                .collect::<Vec<_>>()
+                .join(";"),
+            "collection_id" => b.collection_ids
+                .iter()
+                .map(|id| id.to_hex())
+                .collect::<Vec<_>>()
+                .join(";"),
+            "created_at" => b.created_at.to_rfc3339(),
+            "updated_at" => b.updated_at.to_rfc3339(),
+            _ => String::new(),
+        }
+    }
+}
+
+
+fn parse_columns(columns: Option<&str>) -> Result<Vec<String>, (StatusCode, String)> {
+
+    let Some(columns) = columns else {
+        return Ok(DEFAULT_CSV_COLUMNS.iter().map(|c| c.to_string()).collect());
+    };
+
+    let columns: Vec<String> = columns
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    if columns.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No columns selected".to_string()));
+    }
+
+    if let Some(unknown) = columns.iter().find(|c| !CSV_COLUMNS.contains(&c.as_str())) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown column: {}", unknown)));
+    }
+
+    Ok(columns)
+}
+
+
+// Export bookmarks, streamed straight from the cursor
+pub async fn export_bookmarks(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let columns = match query.format {
+        ExportFormat::Csv => parse_columns(query.columns.as_deref())?,
+        _ => vec![],
+    };
+
+    let filter = bookmark_filter(user_id, &query.filter)?;
+
+    // Collection names are needed for grouping and the CSV collection column,
+    // nested collections are written with their full path. Bookmarks can also
+    // be filed in collections others shared with the user
+    let mut collections = user_collections(&state, user_id).await?;
+    collections.extend(shared_collections(&state, user_id).await?.into_values().map(|(col, _)| col));
+
+    let collection_names: HashMap<ObjectId, String> = collection_path_names(&collections)
+        .into_iter()
+        .map(|(id, path)| (id, path.join(" / ")))
+        .collect();
+
+    let mut pipeline = vec![doc! { "$match": filter }];
+
+    // Grouped formats list a bookmark under each of its collections: one row per
+    // collection, whose collection_ids then only holds that collection
+    if matches!(query.format, ExportFormat::Markdown | ExportFormat::Opml) {
+        pipeline.push(doc! { "$unwind": { "path": "$collection_ids", "preserveNullAndEmptyArrays": true } });
+        pipeline.push(doc! { "$sort": { "collection_ids": 1, "created_at": -1 } });
+        pipeline.push(doc! { "$set": { "collection_ids": {
+            "$cond": [{ "$eq": [{ "$type": "$collection_ids" }, "objectId"] }, ["$collection_ids"], []]
+        } } });
+    } else {
+        pipeline.push(doc! { "$sort": { "created_at": -1 } });
+    }
+
+    // Sorting the unwound rows of a large library can pass the in-memory limit
+    let options = AggregateOptions::builder()
+        .allow_disk_use(true)
+        .build();
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let cursor = bookmarks_col
+        .aggregate(pipeline, options)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .with_type::<Bookmark>();
+
+    let renderer = Renderer {
+        format: query.format,
+        columns,
+        collection_names,
+        current_group: None,
+    };
+
+    let (content_type, file_name) = match query.format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "bookmarks.csv"),
+        ExportFormat::Markdown => ("text/markdown; charset=utf-8", "bookmarks.md"),
+        ExportFormat::Opml => ("text/x-opml; charset=utf-8", "bookmarks.opml"),
+        ExportFormat::Urls => ("text/plain; charset=utf-8", "bookmarks.txt"),
+    };
+
+    let body = Body::from_stream(render_stream(cursor, renderer));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        body,
+    ).into_response())
+}
+
+
+enum RenderStage {
+    Header,
+    Rows,
+    Done,
+}
+
+fn render_stream(
+    cursor: Cursor<Bookmark>,
+    renderer: Renderer,
+) -> impl futures::Stream<Item = Result<Bytes, mongodb::error::Error>> {
+
+    stream::try_unfold(
+        (cursor, renderer, RenderStage::Header),
+        |(mut cursor, mut renderer, stage)| async move {
+            match stage {
+                RenderStage::Header => {
+                    let header = renderer.header();
+                    Ok(Some((Bytes::from(header), (cursor, renderer, RenderStage::Rows))))
+                }
+                RenderStage::Rows => match cursor.try_next().await? {
+                    Some(bookmark) => {
+                        let chunk = renderer.bookmark(&bookmark);
+                        Ok(Some((Bytes::from(chunk), (cursor, renderer, RenderStage::Rows))))
+                    }
+                    None => {
+                        let footer = renderer.footer();
+                        Ok(Some((Bytes::from(footer), (cursor, renderer, RenderStage::Done))))
+                    }
+                },
+                RenderStage::Done => Ok(None),
+            }
+        },
+    )
+}
@@ -0,0 +1,362 @@
+use axum::{
+    extract::{State, Path},
+    http::{StatusCode, HeaderMap, header},
+    response::{IntoResponse, Response},
+    Json,
+    Extension,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::FindOptions;
+use futures::TryStreamExt;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::state::app_state::AppState;
+use crate::models::feed::{Feed, FeedKind, CreateFeedRequest, FeedResponse};
+use crate::models::bookmark::Bookmark;
+use crate::models::member::MemberRole;
+use crate::handlers::member::{require_role, collection_role};
+use crate::utils::jwt::Claims;
+use crate::utils::escape;
+use crate::utils::filter::{collection_bookmark_filter, tag_match};
+use crate::utils::tags::{load_tag_settings, normalize_tag};
+
+// Number of bookmarks in a feed
+const FEED_SIZE: i64 = 50;
+
+#[derive(Clone, Copy)]
+enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+
+// Create a feed for a collection or a tag
+pub async fn create_feed(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(body): Json<CreateFeedRequest>,
+) -> Result<(StatusCode, Json<FeedResponse>), (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let (kind, collection_id, tag) = match (&body.collection_id, &body.tag) {
+        (Some(id), None) => {
+            let collection_id = ObjectId::parse_str(id)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+            require_role(&state, user_id, collection_id, MemberRole::Viewer).await?;
+            (FeedKind::Collection, Some(collection_id), None)
+        }
+        (None, Some(tag)) if !tag.trim().is_empty() => {
+            // Stored like bookmark tags, so the feed matches them
+            let settings = load_tag_settings(&state.db, user_id).await?;
+            let tag = normalize_tag(tag, &settings)
+                .ok_or((StatusCode::BAD_REQUEST, "Provide either collection_id or tag".to_string()))?;
+            (FeedKind::Tag, None, Some(tag))
+        }
+        _ => return Err((StatusCode::BAD_REQUEST, "Provide either collection_id or tag".to_string())),
+    };
+
+    let mut feed = Feed {
+        id: None,
+        user_id,
+        kind,
+        collection_id,
+        tag,
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        etag: None,
+        changed_at: None,
+        created_at: Utc::now(),
+    };
+
+    let result = state.db.collection::<Feed>("feeds")
+        .insert_one(&feed, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create feed".to_string()))?;
+
+    feed.id = Some(result.inserted_id.as_object_id().unwrap());
+
+    Ok((StatusCode::CREATED, Json(FeedResponse::new(feed, &state.public_url))))
+}
+
+
+// List the user's feeds
+pub async fn list_feeds(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<FeedResponse>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let cursor = state.db.collection::<Feed>("feeds")
+        .find(doc! { "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let feeds: Vec<Feed> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch feeds".to_string()))?;
+
+    Ok(Json(feeds.into_iter().map(|f| FeedResponse::new(f, &state.public_url)).collect()))
+}
+
+
+// Delete a feed, its token stops working
+pub async fn delete_feed(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let feed_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid feed id".to_string()))?;
+
+    let result = state.db.collection::<Feed>("feeds")
+        .delete_one(doc! { "_id": feed_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete feed".to_string()))?;
+
+    if result.deleted_count == 0 {
+        return Err((StatusCode::NOT_FOUND, "Feed not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+pub async fn atom_feed(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    render_feed(&state, &token, &headers, FeedFormat::Atom).await
+}
+
+
+pub async fn rss_feed(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    render_feed(&state, &token, &headers, FeedFormat::Rss).await
+}
+
+
+async fn render_feed(
+    state: &AppState,
+    token: &str,
+    headers: &HeaderMap,
+    format: FeedFormat,
+) -> Result<Response, (StatusCode, String)> {
+
+    let not_found = (StatusCode::NOT_FOUND, "Feed not found".to_string());
+
+    let feed = state.db.collection::<Feed>("feeds")
+        .find_one(doc! { "token": token }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or(not_found.clone())?;
+
+    // Access is checked again on every read, a revoked membership ends the feed
+    let (title, filter, col_updated_at) = match feed.kind {
+        FeedKind::Collection => {
+            let collection_id = feed.collection_id.unwrap();
+            let (col, _) = collection_role(state, feed.user_id, collection_id)
+                .await?
+                .ok_or(not_found)?;
+            let filter = collection_bookmark_filter(&col)?;
+            (col.name, filter, Some(col.updated_at))
+        }
+        FeedKind::Tag => {
+            let tag = feed.tag.clone().unwrap();
+            (format!("#{}", tag), doc! { "user_id": feed.user_id, "tags": tag_match(&tag) }, None)
+        }
+    };
+
+    let cursor = state.db.collection::<Bookmark>("bookmarks")
+        .find(
+            filter,
+            FindOptions::builder()
+                .sort(doc! { "created_at": -1 })
+                .limit(FEED_SIZE)
+                .build(),
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let bookmarks: Vec<Bookmark> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bookmarks".to_string()))?;
+
+    // Changes whenever a bookmark is added, edited or removed. SHA-256 gives
+    // the same tag across builds, so caches stay valid over a restart
+    let mut hasher = Sha256::new();
+    hasher.update(title.as_bytes());
+    for b in &bookmarks {
+        hasher.update(b.id.unwrap().bytes());
+        hasher.update(b.updated_at.timestamp_millis().to_be_bytes());
+    }
+    let digest = hasher.finalize();
+    let etag = format!(
+        "\"{}\"",
+        digest[..16].iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+    );
+
+    // A removed bookmark leaves no updated_at behind, so remember when the
+    // served content last changed
+    let mut changed_at = feed.changed_at;
+
+    if feed.etag.as_deref() != Some(etag.as_str()) {
+        let mut set_doc = doc! { "etag": &etag };
+        if feed.etag.is_some() {
+            let now = Utc::now();
+            set_doc.insert("changed_at", now.to_rfc3339());
+            changed_at = Some(now);
+        }
+        let _ = state.db.collection::<Feed>("feeds")
+            .update_one(doc! { "_id": feed.id }, doc! { "$set": set_doc }, None)
+            .await;
+    }
+
+    let last_modified = bookmarks
+        .iter()
+        .map(|b| b.updated_at)
+        .chain(col_updated_at)
+        .chain(changed_at)
+        .max()
+        .unwrap_or(feed.created_at);
+
+    let last_modified_header = http_date(last_modified);
+
+    if not_modified(headers, &etag, last_modified) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag),
+                (header::LAST_MODIFIED, last_modified_header),
+            ],
+        ).into_response());
+    }
+
+    let self_url = format!(
+        "{}/feeds/{}/{}",
+        state.public_url,
+        token,
+        match format { FeedFormat::Atom => "atom", FeedFormat::Rss => "rss" },
+    );
+
+    let (content_type, body) = match format {
+        FeedFormat::Atom => (
+            "application/atom+xml; charset=utf-8",
+            atom(&feed, &title, &self_url, last_modified, &bookmarks),
+        ),
+        FeedFormat::Rss => (
+            "application/rss+xml; charset=utf-8",
+            rss(&title, &self_url, &state.public_url, last_modified, &bookmarks),
+        ),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::ETAG, etag),
+            (header::LAST_MODIFIED, last_modified_header),
+            (header::CACHE_CONTROL, "private, max-age=300".to_string()),
+        ],
+        body,
+    ).into_response())
+}
+
+
+// If-None-Match wins over If-Modified-Since, as in RFC 9110
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: DateTime<Utc>) -> bool {
+
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return if_none_match
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == etag || t == "*");
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+        // HTTP dates have no fractional seconds
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+
+fn atom(feed: &Feed, title: &str, self_url: &str, updated: DateTime<Utc>, bookmarks: &[Bookmark]) -> String {
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+
+    out.push_str(&format!("<id>urn:bookmark-feed:{}</id>\n", feed.id.unwrap().to_hex()));
+    out.push_str(&format!("<title>{}</title>\n", escape::xml(title)));
+    out.push_str(&format!("<updated>{}</updated>\n", updated.to_rfc3339()));
+    out.push_str(&format!("<link rel=\"self\" href=\"{}\"/>\n", escape::xml(self_url)));
+    out.push_str("<author><name>Bookmarks</name></author>\n");
+
+    for b in bookmarks {
+        out.push_str("<entry>\n");
+        out.push_str(&format!("<id>urn:bookmark:{}</id>\n", b.id.unwrap().to_hex()));
+        out.push_str(&format!("<title>{}</title>\n", escape::xml(&b.title)));
+        out.push_str(&format!("<link href=\"{}\"/>\n", escape::xml(&b.url)));
+        out.push_str(&format!("<published>{}</published>\n", b.created_at.to_rfc3339()));
+        out.push_str(&format!("<updated>{}</updated>\n", b.updated_at.to_rfc3339()));
+        if let Some(description) = b.description.as_deref().filter(|d| !d.is_empty()) {
+            out.push_str(&format!("<summary>{}</summary>\n", escape::xml(description)));
+        }
+        for tag in &b.tags {
+            out.push_str(&format!("<category term=\"{}\"/>\n", escape::xml(tag)));
+        }
+        out.push_str("</entry>\n");
+    }
+
+    out.push_str("</feed>\n");
+    out
+}
+
+
+fn rss(title: &str, self_url: &str, site_url: &str, updated: DateTime<Utc>, bookmarks: &[Bookmark]) -> String {
+
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n",
+    );
+
+    out.push_str(&format!("<title>{}</title>\n", escape::xml(title)));
+    out.push_str(&format!("<link>{}</link>\n", escape::xml(site_url)));
+    out.push_str(&format!("<description>Bookmarks in {}</description>\n", escape::xml(title)));
+    out.push_str(&format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape::xml(self_url)));
+    out.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", updated.to_rfc2822()));
+
+    for b in bookmarks {
+        out.push_str("<item>\n");
+        out.push_str(&format!("<title>{}</title>\n", escape::xml(&b.title)));
+        out.push_str(&format!("<link>{}</link>\n", escape::xml(&b.url)));
+        out.push_str(&format!("<guid isPermaLink=\"false\">urn:bookmark:{}</guid>\n", b.id.unwrap().to_hex()));
+        out.push_str(&format!("<pubDate>{}</pubDate>\n", b.created_at.to_rfc2822()));
+        if let Some(description) = b.description.as_deref().filter(|d| !d.is_empty()) {
+            out.push_str(&format!("<description>{}</description>\n", escape::xml(description)));
+        }
+        for tag in &b.tags {
+            out.push_str(&format!("<category>{}</category>\n", escape::xml(tag)));
+        }
+        out.push_str("</item>\n");
+    }
+
+    out.push_str("</channel>\n</rss>\n");
+    out
+}
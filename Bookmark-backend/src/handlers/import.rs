@@ -1,236 +1,1608 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
 use axum::{
-    extract::State,
+    extract::{State, Path, Query},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
     Extension,
 };
 use axum_extra::extract::Multipart;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use mongodb::error::{TRANSIENT_TRANSACTION_ERROR, RETRYABLE_WRITE_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
+use mongodb::ClientSession;
 use chrono::Utc;
-use scraper::{Html, Selector};
+use futures::TryStreamExt;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
 
 use crate::state::app_state::AppState;
 use crate::models::bookmark::Bookmark;
-use crate::models::collection::Collection;
+use crate::models::collection::{Collection, CollectionKind};
+use crate::handlers::collection::{user_collections, collection_paths};
+use crate::models::import::{
+    ParsedBookmark,
+    ImportJob,
+    ImportJobResponse,
+    ImportStatus,
+    ImportItemError,
+    ImportItem,
+    ImportResolution,
+    ImportPreview,
+    PreviewFolder,
+    PreviewConflict,
+    ConflictAction,
+};
 use crate::utils::jwt::Claims;
+use crate::utils::firefox::{is_places_sqlite, parse_places_sqlite};
+use crate::utils::upload::{too_large, decompress, decode_html};
+use crate::utils::tags::{load_tag_settings, normalize_tags, register_tags};
+use crate::utils::ordering::{
+    ListScope,
+    key_between,
+    append_position_with_session,
+    bookmark_scope,
+    bookmark_list_key,
+    collection_scope,
+};
 
 
-// Parsed bookmark from HTML
-struct ParsedBookmark {
-    title: String,
-    url: String,
-    folder: Option<String>,
-}
-
-
-// Parse browser HTML export
+// Parse browser HTML export, keeping the folder nesting
 fn parse_bookmarks_html(html: &str) -> Vec<ParsedBookmark> {
 
     let document = Html::parse_document(html);
     let mut bookmarks = Vec::new();
-    let mut current_folder: Option<String> = None;
 
-    // Select all h3 (folders) and a (links)
-    let h3_selector = Selector::parse("h3").unwrap();
-    let a_selector = Selector::parse("a").unwrap();
-    let dl_selector = Selector::parse("dl").unwrap();
+    let body_selector = Selector::parse("body").unwrap();
+
+    if let Some(body) = document.select(&body_selector).next() {
+        walk_bookmarks_html(body, &mut Vec::new(), &mut bookmarks);
+    }
+
+    bookmarks
+}
 
-    // Get all DL elements (folders)
-    let dls: Vec<_> = document.select(&dl_selector).collect();
 
-    for dl in dls {
+// Folders are an <H3> followed by a <DL> holding their contents
+fn walk_bookmarks_html(element: ElementRef, path: &mut Vec<String>, bookmarks: &mut Vec<ParsedBookmark>) {
 
-        // Check if there is an H3 before this DL (folder name)
-        let folder_name = dl
-            .prev_siblings()
-            .find(|s| {
-                s.value().is_element()
-            })
-            .and_then(|s| {
-                let el = scraper::ElementRef::wrap(s)?;
-                if el.value().name() == "h3" {
-                    Some(el.text().collect::<String>())
-                } else {
-                    None
+    let mut folder_name: Option<String> = None;
+
+    for child in element.children().filter_map(ElementRef::wrap) {
+        match child.value().name() {
+            "h3" => {
+                folder_name = Some(child.text().collect::<String>().trim().to_string());
+            }
+            "a" => {
+                let url = child.value().attr("href").unwrap_or("").to_string();
+                let title = child.text().collect::<String>();
+
+                if url.is_empty() {
+                    continue;
                 }
-            });
 
-        if folder_name.is_some() {
-            current_folder = folder_name;
-        }
+                // Firefox exports carry tags, keywords and dates as attributes
+                let tags = child.value()
+                    .attr("tags")
+                    .map(|t| t.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+                    .unwrap_or_default();
 
-        // Get all links in this DL
-        for a in dl.select(&a_selector) {
-            let url = a.value().attr("href").unwrap_or("").to_string();
-            let title = a.text().collect::<String>();
+                let added_at = child.value()
+                    .attr("add_date")
+                    .and_then(|d| d.parse::<i64>().ok())
+                    .and_then(|d| chrono::DateTime::from_timestamp(d, 0));
 
-            if url.starts_with("http") {
                 bookmarks.push(ParsedBookmark {
                     title: if title.is_empty() { url.clone() } else { title },
                     url,
-                    folder: current_folder.clone(),
+                    folder_path: path.clone(),
+                    tags,
+                    keyword: child.value().attr("shortcuturl").map(|k| k.to_string()),
+                    added_at,
                 });
             }
+            "dl" => match folder_name.take() {
+                Some(name) => {
+                    path.push(if name.is_empty() { "Untitled".to_string() } else { name });
+                    walk_bookmarks_html(child, path, bookmarks);
+                    path.pop();
+                }
+                None => walk_bookmarks_html(child, path, bookmarks),
+            },
+            _ => walk_bookmarks_html(child, path, bookmarks),
         }
     }
+}
 
-    // Also get top level links (not in any folder)
-    let top_level: Vec<_> = document.select(&a_selector).collect();
-    for a in top_level {
-        let url = a.value().attr("href").unwrap_or("").to_string();
-        let title = a.text().collect::<String>();
 
-        if url.starts_with("http") {
-            let already_added = bookmarks.iter().any(|b| b.url == url);
-            if !already_added {
-                bookmarks.push(ParsedBookmark {
-                    title: if title.is_empty() { url.clone() } else { title },
-                    url,
-                    folder: None,
-                });
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+
+// Bookmarks written per insert_many, the job also
+// reports progress and checks for cancellation after each batch
+const BATCH_SIZE: usize = 500;
+
+// Tries of an import that fails with a transient error
+const MAX_IMPORT_ATTEMPTS: usize = 3;
+
+// How often a running job refreshes its heartbeat
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+// Jobs without a heartbeat for this long are failed
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How often stale jobs are looked for
+pub const STALE_JOB_CHECK: Duration = Duration::from_secs(60);
+
+// Previews that are neither committed nor cancelled are removed after this long
+pub const PREVIEW_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+
+// Stream the "file" field of the upload, stopping as soon as it passes the limit
+async fn read_upload(multipart: &mut Multipart, limit: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+
+    let mut file_bytes: Vec<u8> = Vec::new();
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to read file".to_string()))?
+    {
+        let name = field.name().unwrap_or("").to_string();
+
+        if name != "file" {
+            continue;
+        }
+
+        file_bytes.clear();
+
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to read file bytes".to_string()))?
+        {
+            if file_bytes.len() + chunk.len() > limit {
+                return Err(too_large(limit));
             }
+
+            file_bytes.extend_from_slice(&chunk);
         }
     }
 
-    bookmarks
+    if file_bytes.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No file uploaded".to_string()));
+    }
+
+    Ok(file_bytes)
+}
+
+
+// Parse an HTML export or a Firefox places.sqlite, either may be gzip or zip compressed
+async fn parse_upload(file_bytes: Vec<u8>, limit: usize) -> Result<Vec<ParsedBookmark>, (StatusCode, String)> {
+
+    // Decompressing and parsing are CPU bound and SQLite is blocking,
+    // so keep all of it off the runtime
+    tokio::task::spawn_blocking(move || {
+
+        let file_bytes = decompress(file_bytes, limit)?;
+
+        if is_places_sqlite(&file_bytes) {
+            return parse_places_sqlite(&file_bytes)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e));
+        }
+
+        let html_content = decode_html(&file_bytes);
+
+        Ok(parse_bookmarks_html(&html_content))
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse file".to_string()))?
 }
 
 
+// Split parsed entries into importable ones and invalid ones, repeats count as invalid
+fn validate_parsed(parsed: Vec<ParsedBookmark>) -> (Vec<ParsedBookmark>, Vec<ImportItemError>) {
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut valid = Vec::new();
+    let mut invalid = Vec::new();
+
+    for bookmark in parsed {
+
+        let error = if !seen.insert(bookmark.url.clone()) {
+            Some("Duplicate URL in file")
+        } else if !(bookmark.url.starts_with("http://") || bookmark.url.starts_with("https://")) {
+            Some("Unsupported URL scheme")
+        } else if !validator::validate_url(&bookmark.url) {
+            Some("Invalid URL")
+        } else {
+            None
+        };
+
+        match error {
+            Some(error) => invalid.push(ImportItemError {
+                url: bookmark.url,
+                error: error.to_string(),
+            }),
+            None => valid.push(bookmark),
+        }
+    }
+
+    (valid, invalid)
+}
+
+
+// Start an import job, or with dry_run only report what would happen
 pub async fn import_bookmarks(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
+    Query(query): Query<ImportQuery>,
     mut multipart: Multipart,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
-    // Get uploaded file
-    let mut html_content = String::new();
+    let file_bytes = read_upload(&mut multipart, state.max_upload_bytes).await?;
 
-    while let Some(field) = multipart
-        .next_field()
+    let parsed = parse_upload(file_bytes, state.max_upload_bytes).await?;
+
+    let (mut items, invalid) = validate_parsed(parsed);
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+    for item in &mut items {
+        item.tags = normalize_tags(&item.tags, &settings);
+    }
+
+    if items.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No bookmarks found in file".to_string()));
+    }
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let status = if query.dry_run {
+        ImportStatus::Preview
+    } else {
+        ImportStatus::Pending
+    };
+
+    // Previews wait for the user, no process is working on them yet
+    let worker_id = (!query.dry_run).then(|| state.instance_id.clone());
+    let heartbeat_at = (!query.dry_run).then(mongodb::bson::DateTime::now);
+
+    let now = Utc::now();
+    let mut job = ImportJob {
+        id: None,
+        user_id,
+        status,
+        total: items.len() as i64,
+        processed: 0,
+        imported: 0,
+        skipped: 0,
+        updated: 0,
+        collections_created: 0,
+        errors: invalid.clone(),
+        message: None,
+        worker_id,
+        heartbeat_at,
+        created_at: now,
+        updated_at: now,
+        finished_at: None,
+    };
+
+    let result = jobs_col
+        .insert_one(&job, None)
         .await
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to read file".to_string()))?
-    {
-        let name = field.name().unwrap_or("").to_string();
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create import job".to_string()))?;
+
+    let job_id = result.inserted_id.as_object_id().unwrap();
+    job.id = Some(job_id);
+
+    if query.dry_run {
+        // The parsed entries wait in import_items until the job is committed
+        store_items(&state, job_id, &items).await?;
+
+        let preview = build_preview(&state, job_id, user_id, &items, invalid).await?;
+        return Ok(Json(preview).into_response());
+    }
+
+    tokio::spawn(run_import_job(state, job_id, user_id, items, ImportResolution::default(), invalid));
+
+    Ok((StatusCode::ACCEPTED, Json(ImportJobResponse::from(job))).into_response())
+}
 
-        if name == "file" {
-            let bytes = field
-                .bytes()
-                .await
-                .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to read file bytes".to_string()))?;
 
-            html_content = String::from_utf8(bytes.to_vec())
-                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid file encoding".to_string()))?;
+// Work out which folders and URLs already exist
+async fn build_preview(
+    state: &AppState,
+    job_id: ObjectId,
+    user_id: ObjectId,
+    items: &[ParsedBookmark],
+    invalid: Vec<ImportItemError>,
+) -> Result<ImportPreview, (StatusCode, String)> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let mut existing: HashMap<String, Bookmark> = HashMap::new();
+
+    // Large files are looked up in chunks to keep each query small
+    for chunk in items.chunks(BATCH_SIZE) {
+        let urls: Vec<&str> = chunk.iter().map(|b| b.url.as_str()).collect();
+
+        let cursor = bookmarks_col
+            .find(doc! { "user_id": user_id, "url": { "$in": urls } }, None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+        let found: Vec<Bookmark> = cursor
+            .try_collect()
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bookmarks".to_string()))?;
+
+        existing.extend(found.into_iter().map(|b| (b.url.clone(), b)));
+    }
+
+    // Folders in the order they appear in the file, with their direct bookmark counts
+    let mut folder_paths: Vec<Vec<String>> = Vec::new();
+    let mut folder_counts: HashMap<Vec<String>, usize> = HashMap::new();
+
+    for item in items {
+        for depth in 1..=item.folder_path.len() {
+            let path = item.folder_path[..depth].to_vec();
+            if !folder_counts.contains_key(&path) {
+                folder_counts.insert(path.clone(), 0);
+                folder_paths.push(path);
+            }
+        }
+        if let Some(count) = folder_counts.get_mut(&item.folder_path) {
+            *count += 1;
         }
     }
 
-    if html_content.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "No file uploaded".to_string()));
+    let existing_cols = collection_paths(&user_collections(state, user_id).await?);
+
+    let folders = preview_folders(&[], &folder_paths, &folder_counts, &existing_cols);
+
+    let conflicts: Vec<PreviewConflict> = items
+        .iter()
+        .filter_map(|item| {
+            let current = existing.get(&item.url)?;
+            Some(PreviewConflict {
+                url: item.url.clone(),
+                title: item.title.clone(),
+                existing_id: current.id.unwrap().to_hex(),
+                existing_title: current.title.clone(),
+                existing_tags: current.tags.clone(),
+            })
+        })
+        .collect();
+
+    Ok(ImportPreview {
+        job_id: job_id.to_hex(),
+        total: items.len(),
+        new_bookmarks: items.len() - conflicts.len(),
+        unfiled: items.iter().filter(|b| b.folder_path.is_empty()).count(),
+        folders,
+        conflicts,
+        invalid,
+    })
+}
+
+
+fn preview_folders(
+    parent: &[String],
+    folder_paths: &[Vec<String>],
+    folder_counts: &HashMap<Vec<String>, usize>,
+    existing_cols: &HashMap<Vec<String>, ObjectId>,
+) -> Vec<PreviewFolder> {
+
+    folder_paths
+        .iter()
+        .filter(|path| path.len() == parent.len() + 1 && path.starts_with(parent))
+        .map(|path| {
+            let collection_id = existing_cols.get(path).map(|id| id.to_hex());
+            PreviewFolder {
+                name: path.last().cloned().unwrap_or_default(),
+                path: path.clone(),
+                bookmark_count: folder_counts[path],
+                action: if collection_id.is_some() { "reuse" } else { "create" }.to_string(),
+                collection_id,
+                children: preview_folders(path, folder_paths, folder_counts, existing_cols),
+            }
+        })
+        .collect()
+}
+
+
+// Save the entries of a dry run in file order, one document each
+async fn store_items(
+    state: &AppState,
+    job_id: ObjectId,
+    items: &[ParsedBookmark],
+) -> Result<(), (StatusCode, String)> {
+
+    let items_col = state.db.collection::<ImportItem>("import_items");
+
+    let created_at = mongodb::bson::DateTime::now();
+
+    for (chunk_index, chunk) in items.chunks(BATCH_SIZE).enumerate() {
+        let docs: Vec<ImportItem> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, bookmark)| ImportItem {
+                id: None,
+                job_id,
+                position: (chunk_index * BATCH_SIZE + i) as i64,
+                bookmark: bookmark.clone(),
+                created_at,
+            })
+            .collect();
+
+        items_col
+            .insert_many(docs, None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store import preview".to_string()))?;
     }
 
-    // Parse bookmarks from HTML
-    let parsed = parse_bookmarks_html(&html_content);
+    Ok(())
+}
+
 
-    if parsed.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "No bookmarks found in file".to_string()));
+async fn load_items(
+    state: &AppState,
+    job_id: ObjectId,
+) -> Result<Vec<ParsedBookmark>, (StatusCode, String)> {
+
+    let items_col = state.db.collection::<ImportItem>("import_items");
+
+    let options = FindOptions::builder()
+        .sort(doc! { "position": 1 })
+        .build();
+
+    let cursor = items_col
+        .find(doc! { "job_id": job_id }, options)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let items: Vec<ImportItem> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch import preview".to_string()))?;
+
+    Ok(items.into_iter().map(|item| item.bookmark).collect())
+}
+
+
+// Left over entries are harmless, so failures are ignored
+async fn clear_items(state: &AppState, job_id: ObjectId) {
+
+    let _ = state.db
+        .collection::<ImportItem>("import_items")
+        .delete_many(doc! { "job_id": job_id }, None)
+        .await;
+}
+
+
+// Run a previewed import, applying the chosen conflict resolutions
+pub async fn commit_import(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(resolution): Json<ImportResolution>,
+) -> Result<(StatusCode, Json<ImportJobResponse>), (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let job_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid job id".to_string()))?;
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let mut job = jobs_col
+        .find_one(doc! { "_id": job_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Import job not found".to_string()))?;
+
+    let items = load_items(&state, job_id).await?;
+
+    // The entries expire shortly before the job itself is removed
+    if job.status == ImportStatus::Preview && items.is_empty() {
+        return Err((StatusCode::GONE, "Import preview expired".to_string()));
     }
 
-    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
-    let collections_col = state.db.collection::<Collection>("collections");
+    // Only move on from preview once, even if commit is sent twice
+    let result = jobs_col
+        .update_one(
+            doc! { "_id": job_id, "status": "preview" },
+            doc! { "$set": {
+                "status": "pending",
+                "worker_id": &state.instance_id,
+                "heartbeat_at": mongodb::bson::DateTime::now(),
+                "updated_at": Utc::now().to_rfc3339()
+            } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start import job".to_string()))?;
+
+    if result.matched_count == 0 {
+        return Err((StatusCode::CONFLICT, "Import job is not awaiting commit".to_string()));
+    }
+
+    // The worker has its own copy now
+    clear_items(&state, job_id).await;
+
+    job.status = ImportStatus::Pending;
+
+    tokio::spawn(run_import_job(state, job_id, user_id, items, resolution, job.errors.clone()));
+
+    Ok((StatusCode::ACCEPTED, Json(ImportJobResponse::from(job))))
+}
+
+
+// Progress counters of a running job
+#[derive(Default, Clone)]
+struct ImportProgress {
+    processed: i64,
+    imported: i64,
+    skipped: i64,
+    updated: i64,
+    collections_created: i64,
+    errors: Vec<ImportItemError>,
+    // Tags of the saved bookmarks, recorded in the tags collection at the end
+    tags: BTreeSet<String>,
+}
 
-    let mut folder_map: std::collections::HashMap<String, ObjectId> = std::collections::HashMap::new();
-    let mut imported_count = 0;
-    let mut skipped_count = 0;
+async fn run_import_job(
+    state: AppState,
+    job_id: ObjectId,
+    user_id: ObjectId,
+    items: Vec<ParsedBookmark>,
+    resolution: ImportResolution,
+    errors: Vec<ImportItemError>,
+) {
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    // Claim the job, this fails if it was cancelled before it started
+    let claimed = jobs_col
+        .update_one(
+            doc! { "_id": job_id, "status": "pending" },
+            doc! { "$set": {
+                "status": "running",
+                "worker_id": &state.instance_id,
+                "heartbeat_at": mongodb::bson::DateTime::now(),
+                "updated_at": Utc::now().to_rfc3339()
+            } },
+            None,
+        )
+        .await;
+
+    if !matches!(claimed, Ok(r) if r.matched_count == 1) {
+        return;
+    }
 
-    for parsed_bookmark in parsed {
+    // Keeps other processes from failing the job while a batch takes long
+    let heartbeat = tokio::spawn(beat(jobs_col.clone(), job_id));
 
-        // Skip if URL already exists for this user
-        let existing = bookmarks_col
-            .find_one(
-                doc! { "user_id": user_id, "url": &parsed_bookmark.url },
+    let mut progress = ImportProgress {
+        errors,
+        ..Default::default()
+    };
+
+    let result = import_parsed(&state, job_id, user_id, items, &resolution, &mut progress).await;
+
+    heartbeat.abort();
+
+    // Only metadata, the bookmarks have their tags either way
+    let tags: Vec<String> = std::mem::take(&mut progress.tags).into_iter().collect();
+    let _ = register_tags(&state.db, user_id, &tags).await;
+
+    let status = match result {
+        Ok(true) => ImportStatus::Completed,
+        Ok(false) => ImportStatus::Cancelled,
+        Err(e) => {
+            progress_update(&state, job_id, &progress, Some(ImportStatus::Failed), Some(e)).await;
+            return;
+        }
+    };
+
+    progress_update(&state, job_id, &progress, Some(status), None).await;
+}
+
+
+// Refreshes the heartbeat of a job until the task is aborted
+async fn beat(jobs_col: mongodb::Collection<ImportJob>, job_id: ObjectId) {
+
+    loop {
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+
+        let _ = jobs_col
+            .update_one(
+                doc! { "_id": job_id, "status": "running" },
+                doc! { "$set": { "heartbeat_at": mongodb::bson::DateTime::now() } },
                 None,
             )
+            .await;
+    }
+}
+
+
+// Write counters to the job. Returns false once the job is no longer running
+async fn progress_update(
+    state: &AppState,
+    job_id: ObjectId,
+    progress: &ImportProgress,
+    finish: Option<ImportStatus>,
+    message: Option<String>,
+) -> bool {
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let now = Utc::now().to_rfc3339();
+
+    let mut set_doc = doc! {
+        "processed": progress.processed,
+        "imported": progress.imported,
+        "skipped": progress.skipped,
+        "updated": progress.updated,
+        "collections_created": progress.collections_created,
+        "errors": mongodb::bson::to_bson(&progress.errors).unwrap_or_default(),
+        "updated_at": &now,
+    };
+
+    // A cancelled job keeps its status, only the final counters are written
+    let filter = match finish {
+        Some(ImportStatus::Cancelled) => doc! { "_id": job_id },
+        _ => doc! { "_id": job_id, "status": "running" },
+    };
+
+    if let Some(status) = finish {
+        if status != ImportStatus::Cancelled {
+            set_doc.insert("status", mongodb::bson::to_bson(&status).unwrap_or_default());
+        }
+        set_doc.insert("finished_at", &now);
+    }
+    if let Some(message) = message {
+        set_doc.insert("message", message);
+    }
+
+    match jobs_col.update_one(filter, doc! { "$set": set_doc }, None).await {
+        Ok(result) => result.matched_count == 1,
+        Err(_) => true,
+    }
+}
+
+
+// Existing bookmark ids by URL, fetched in chunks rather than per link
+async fn existing_urls(
+    state: &AppState,
+    user_id: ObjectId,
+    items: &[ParsedBookmark],
+) -> Result<HashMap<String, ObjectId>, String> {
+
+    let bookmarks_col = state.db.collection::<mongodb::bson::Document>("bookmarks");
+
+    let options = FindOptions::builder()
+        .projection(doc! { "_id": 1, "url": 1 })
+        .build();
+
+    let mut existing = HashMap::new();
+
+    for chunk in items.chunks(BATCH_SIZE) {
+        let urls: Vec<&str> = chunk.iter().map(|b| b.url.as_str()).collect();
+
+        let cursor = bookmarks_col
+            .find(doc! { "user_id": user_id, "url": { "$in": urls } }, options.clone())
             .await
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+            .map_err(|_| "Database error".to_string())?;
+
+        let docs: Vec<mongodb::bson::Document> = cursor
+            .try_collect()
+            .await
+            .map_err(|_| "Failed to fetch bookmarks".to_string())?;
+
+        for d in docs {
+            if let (Ok(url), Ok(id)) = (d.get_str("url"), d.get_object_id("_id")) {
+                existing.insert(url.to_string(), id);
+            }
+        }
+    }
+
+    Ok(existing)
+}
+
 
-        if existing.is_some() {
-            skipped_count += 1;
+// Map every folder path to a collection, creating missing ones level by level
+// so parents always exist before their children
+async fn resolve_folders(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    folder_paths: Vec<Vec<String>>,
+    progress: &mut ImportProgress,
+    undo: &mut ImportUndo,
+) -> Result<HashMap<Vec<String>, ObjectId>, String> {
+
+    let collections_col = state.db.collection::<Collection>("collections");
+
+    if folder_paths.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut cursor = collections_col
+        .find_with_session(doc! { "user_id": user_id }, None, session)
+        .await
+        .map_err(|_| "Database error".to_string())?;
+
+    let existing: Vec<Collection> = cursor
+        .stream(session)
+        .try_collect()
+        .await
+        .map_err(|_| "Failed to fetch collections".to_string())?;
+
+    let mut folder_map = collection_paths(&existing);
+
+    let mut missing: Vec<Vec<String>> = Vec::new();
+
+    for path in &folder_paths {
+        for depth in 1..=path.len() {
+            let prefix = path[..depth].to_vec();
+            if !folder_map.contains_key(&prefix) && !missing.contains(&prefix) {
+                missing.push(prefix);
+            }
+        }
+    }
+
+    let max_depth = missing.iter().map(|p| p.len()).max().unwrap_or(0);
+
+    // New folders go last among their siblings, in file order
+    let docs_col = state.db.collection::<Document>("collections");
+    let mut positions: HashMap<Option<ObjectId>, String> = HashMap::new();
+
+    for depth in 1..=max_depth {
+        let level: Vec<&Vec<String>> = missing.iter().filter(|p| p.len() == depth).collect();
+
+        if level.is_empty() {
             continue;
         }
 
-        // Get or create collection for folder
-        let collection_id = if let Some(folder_name) = &parsed_bookmark.folder {
-
-            if let Some(id) = folder_map.get(folder_name) {
-                Some(*id)
-            } else {
-
-                // Check if collection already exists
-                let existing_col = collections_col
-                    .find_one(
-                        doc! { "user_id": user_id, "name": folder_name },
-                        None,
-                    )
-                    .await
-                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
-
-                let col_id = if let Some(col) = existing_col {
-                    col.id.unwrap()
-                } else {
-                    // Create new collection
-                    let now = Utc::now();
-                    let new_col = Collection {
-                        id: None,
-                        user_id,
-                        name: folder_name.clone(),
-                        description: None,
-                        created_at: now,
-                        updated_at: now,
-                    };
-
-                    let result = collections_col
-                        .insert_one(&new_col, None)
-                        .await
-                        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create collection".to_string()))?;
-
-                    result.inserted_id.as_object_id().unwrap()
+        let now = Utc::now();
+        let mut new_cols: Vec<Collection> = Vec::with_capacity(level.len());
+
+        for path in &level {
+            let parent_id = folder_map.get(&path[..depth - 1]).copied();
+            let scope = collection_scope(user_id, parent_id);
+            let position = next_position(&docs_col, session, &mut positions, parent_id, &scope).await?;
+
+            new_cols.push(Collection {
+                id: None,
+                user_id,
+                name: path[depth - 1].clone(),
+                description: None,
+                icon: None,
+                color: None,
+                cover_url: None,
+                pinned: false,
+                archived: false,
+                parent_id,
+                kind: CollectionKind::Manual,
+                position: Some(position),
+                rule: None,
+                share: None,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+
+        let result = collections_col
+            .insert_many_with_session(&new_cols, None, session)
+            .await
+            .map_err(|_| "Failed to create collection".to_string())?;
+
+        for (index, id) in result.inserted_ids {
+            let id = id.as_object_id().unwrap();
+            folder_map.insert(level[index].clone(), id);
+            undo.collections.push(id);
+        }
+
+        progress.collections_created += new_cols.len() as i64;
+    }
+
+    Ok(folder_map)
+}
+
+
+// Why an import attempt stopped
+enum ImportFailure {
+    // A failed write, transient ones are retried with a fresh attempt
+    Write(mongodb::error::Error),
+    Message(String),
+}
+
+impl From<String> for ImportFailure {
+    fn from(message: String) -> Self {
+        ImportFailure::Message(message)
+    }
+}
+
+
+// What an import wrote, so it can be undone when there is no transaction to abort
+#[derive(Default)]
+struct ImportUndo {
+    collections: Vec<ObjectId>,
+    bookmarks: Vec<ObjectId>,
+    // Existing bookmarks as they were before the import changed them
+    originals: Vec<Document>,
+}
+
+
+// Save parsed bookmarks all or nothing. Folders and every batch share one
+// transaction when the server supports it, otherwise the writes of a failed
+// or cancelled import are undone. Returns Ok(false) if the job was cancelled
+async fn import_parsed(
+    state: &AppState,
+    job_id: ObjectId,
+    user_id: ObjectId,
+    items: Vec<ParsedBookmark>,
+    resolution: &ImportResolution,
+    progress: &mut ImportProgress,
+) -> Result<bool, String> {
+
+    let existing = existing_urls(state, user_id, &items).await?;
+
+    let mut session = state.db
+        .collection::<Bookmark>("bookmarks")
+        .client()
+        .start_session(None)
+        .await
+        .map_err(|_| "Failed to start session".to_string())?;
+
+    // Counters go back to this when an attempt is thrown away
+    let initial = progress.clone();
+
+    let mut attempt = 1;
+
+    loop {
+        let mut undo = ImportUndo::default();
+
+        let result = match start_writes(state, &mut session).await {
+            Ok(()) => write_import(
+                state, &mut session, job_id, user_id, &items, &existing, resolution, progress, &mut undo,
+            ).await,
+            Err(e) => Err(ImportFailure::Write(e)),
+        };
+
+        let result = match result {
+            Ok(true) => commit_writes(state, &mut session)
+                .await
+                .map(|_| true)
+                .map_err(ImportFailure::Write),
+            other => other,
+        };
+
+        if matches!(result, Ok(true)) {
+            return Ok(true);
+        }
+
+        // Nothing of a cancelled or failed attempt is kept
+        abort_writes(state, &mut session).await;
+        *progress = initial.clone();
+
+        if undo_writes(state, user_id, &undo).await.is_err() {
+            return Err("Failed to save bookmarks, and part of the import could not be undone".to_string());
+        }
+
+        match result {
+            Ok(_) => return Ok(false),
+            Err(ImportFailure::Write(e)) => {
+                let retry = e.contains_label(TRANSIENT_TRANSACTION_ERROR)
+                    || e.contains_label(RETRYABLE_WRITE_ERROR);
+
+                if !retry || attempt >= MAX_IMPORT_ATTEMPTS {
+                    return Err("Failed to save bookmarks, nothing was imported".to_string());
+                }
+
+                attempt += 1;
+            }
+            Err(ImportFailure::Message(message)) => return Err(message),
+        }
+    }
+}
+
+
+// One attempt at the whole import. Returns Ok(false) if the job was cancelled
+#[allow(clippy::too_many_arguments)]
+async fn write_import(
+    state: &AppState,
+    session: &mut ClientSession,
+    job_id: ObjectId,
+    user_id: ObjectId,
+    items: &[ParsedBookmark],
+    existing: &HashMap<String, ObjectId>,
+    resolution: &ImportResolution,
+    progress: &mut ImportProgress,
+    undo: &mut ImportUndo,
+) -> Result<bool, ImportFailure> {
+
+    // Folders come first so every batch can file into them
+    let folder_paths = saved_folders(items, existing, resolution);
+
+    let folder_map = resolve_folders(state, session, user_id, folder_paths, progress, undo).await?;
+
+    // New bookmarks go last in their collection, in file order
+    let docs_col = state.db.collection::<Document>("bookmarks");
+    let mut positions: HashMap<Option<ObjectId>, String> = HashMap::new();
+
+    for chunk in items.chunks(BATCH_SIZE) {
+
+        let batch = prepare_batch(
+            &docs_col, session, user_id, chunk, existing, resolution, &folder_map, &mut positions,
+        ).await?;
+
+        write_batch(state, session, &batch, undo)
+            .await
+            .map_err(ImportFailure::Write)?;
+
+        progress.processed += chunk.len() as i64;
+        progress.skipped += batch.skipped;
+        progress.imported += batch.inserts.len() as i64;
+        progress.updated += batch.updates.len() as i64;
+        progress.tags.extend(batch.tags);
+
+        if !progress_update(state, job_id, progress, None, None).await {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+
+
+// Folders of the entries that end up saved, in file order
+fn saved_folders(
+    items: &[ParsedBookmark],
+    existing: &HashMap<String, ObjectId>,
+    resolution: &ImportResolution,
+) -> Vec<Vec<String>> {
+
+    let mut folder_paths: Vec<Vec<String>> = Vec::new();
+    let mut seen_folders: HashSet<&Vec<String>> = HashSet::new();
+
+    for item in items {
+        let saved = match existing.get(&item.url) {
+            None => true,
+            Some(_) => matches!(
+                resolution.action_for(&item.url),
+                ConflictAction::Overwrite | ConflictAction::Duplicate
+            ),
+        };
+
+        if saved && !item.folder_path.is_empty() && seen_folders.insert(&item.folder_path) {
+            folder_paths.push(item.folder_path.clone());
+        }
+    }
+
+    folder_paths
+}
+
+
+// Writes of one batch, worked out before they are sent. New bookmarks
+// get their _id here so an import without a transaction can remove them
+#[derive(Default)]
+struct ImportBatch {
+    inserts: Vec<Bookmark>,
+    // Bookmark id with the update to apply
+    updates: Vec<(ObjectId, Document)>,
+    skipped: i64,
+    tags: BTreeSet<String>,
+}
+
+
+#[allow(clippy::too_many_arguments)]
+async fn prepare_batch(
+    docs_col: &mongodb::Collection<Document>,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    chunk: &[ParsedBookmark],
+    existing: &HashMap<String, ObjectId>,
+    resolution: &ImportResolution,
+    folder_map: &HashMap<Vec<String>, ObjectId>,
+    positions: &mut HashMap<Option<ObjectId>, String>,
+) -> Result<ImportBatch, String> {
+
+    let mut batch = ImportBatch::default();
+
+    for parsed_bookmark in chunk {
+
+        // Only ever one of the user's own manual collections, see resolve_folders
+        let collection_id = folder_map.get(&parsed_bookmark.folder_path).copied();
+
+        let conflict = existing
+            .get(&parsed_bookmark.url)
+            .map(|id| (*id, resolution.action_for(&parsed_bookmark.url)));
+
+        if !matches!(conflict, Some((_, ConflictAction::Skip))) {
+            batch.tags.extend(parsed_bookmark.tags.iter().cloned());
+        }
+
+        match conflict {
+            Some((_, ConflictAction::Skip)) => {
+                batch.skipped += 1;
+            }
+            Some((existing_id, ConflictAction::MergeTags)) => batch.updates.push((
+                existing_id,
+                doc! {
+                    "$addToSet": { "tags": { "$each": &parsed_bookmark.tags } },
+                    "$set": { "updated_at": Utc::now().to_rfc3339() }
+                },
+            )),
+            Some((existing_id, ConflictAction::Overwrite)) => {
+                let mut update_doc = doc! {
+                    "title": &parsed_bookmark.title,
+                    "tags": &parsed_bookmark.tags,
+                    "updated_at": Utc::now().to_rfc3339()
                 };
 
-                folder_map.insert(folder_name.clone(), col_id);
-                Some(col_id)
+                if let Some(keyword) = &parsed_bookmark.keyword {
+                    update_doc.insert("keyword", keyword);
+                }
+
+                // Also filed in the folder's collection, last in file order
+                let mut update = doc! {};
+                if let Some(collection_id) = collection_id {
+                    let scope = bookmark_scope(user_id, Some(collection_id));
+                    let position = next_position(docs_col, session, positions, Some(collection_id), &scope).await?;
+                    update_doc.insert(scope.field, position);
+                    update.insert("$addToSet", doc! { "collection_ids": collection_id });
+                }
+                update.insert("$set", update_doc);
+
+                batch.updates.push((existing_id, update));
             }
-        } else {
-            None
+            Some((_, ConflictAction::Duplicate)) | None => {
+                // Create bookmark, keeping the original date when the export has one
+                let scope = bookmark_scope(user_id, collection_id);
+                let position = next_position(docs_col, session, positions, collection_id, &scope).await?;
+                let now = Utc::now();
+                batch.inserts.push(Bookmark {
+                    id: Some(ObjectId::new()),
+                    user_id,
+                    title: parsed_bookmark.title.clone(),
+                    url: parsed_bookmark.url.clone(),
+                    description: None,
+                    tags: parsed_bookmark.tags.clone(),
+                    collection_ids: collection_id.into_iter().collect(),
+                    keyword: parsed_bookmark.keyword.clone(),
+                    positions: HashMap::from([(bookmark_list_key(collection_id), position)]),
+                    created_at: parsed_bookmark.added_at.unwrap_or(now),
+                    updated_at: now,
+                });
+            }
+        }
+    }
+
+    Ok(batch)
+}
+
+
+// Apply one batch, recording what to undo when there is no transaction
+async fn write_batch(
+    state: &AppState,
+    session: &mut ClientSession,
+    batch: &ImportBatch,
+    undo: &mut ImportUndo,
+) -> Result<(), mongodb::error::Error> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    if !state.supports_transactions && !batch.updates.is_empty() {
+        let ids: Vec<ObjectId> = batch.updates.iter().map(|(id, _)| *id).collect();
+
+        let cursor = state.db
+            .collection::<Document>("bookmarks")
+            .find(doc! { "_id": { "$in": ids } }, None)
+            .await?;
+
+        let originals: Vec<Document> = cursor.try_collect().await?;
+        undo.originals.extend(originals);
+    }
+
+    for (existing_id, update_doc) in &batch.updates {
+        bookmarks_col
+            .update_one_with_session(doc! { "_id": existing_id }, update_doc.clone(), None, session)
+            .await?;
+    }
+
+    // Recorded up front, an insert that fails part way may have saved some
+    undo.bookmarks.extend(batch.inserts.iter().filter_map(|b| b.id));
+
+    if !batch.inserts.is_empty() {
+        bookmarks_col
+            .insert_many_with_session(batch.inserts.iter(), None, session)
+            .await?;
+    }
+
+    Ok(())
+}
+
+
+// Put back what an import wrote without a transaction
+async fn undo_writes(
+    state: &AppState,
+    user_id: ObjectId,
+    undo: &ImportUndo,
+) -> Result<(), mongodb::error::Error> {
+
+    if state.supports_transactions {
+        return Ok(());
+    }
+
+    let bookmarks_col = state.db.collection::<Document>("bookmarks");
+
+    for chunk in undo.bookmarks.chunks(BATCH_SIZE) {
+        bookmarks_col
+            .delete_many(doc! { "_id": { "$in": chunk.to_vec() }, "user_id": user_id }, None)
+            .await?;
+    }
+
+    // A bookmark changed by several batches was first saved before the earliest one
+    for original in undo.originals.iter().rev() {
+        if let Ok(id) = original.get_object_id("_id") {
+            bookmarks_col
+                .replace_one(doc! { "_id": id, "user_id": user_id }, original.clone(), None)
+                .await?;
+        }
+    }
+
+    let collections_col = state.db.collection::<Document>("collections");
+
+    for chunk in undo.collections.chunks(BATCH_SIZE) {
+        collections_col
+            .delete_many(doc! { "_id": { "$in": chunk.to_vec() }, "user_id": user_id }, None)
+            .await?;
+    }
+
+    Ok(())
+}
+
+
+async fn start_writes(state: &AppState, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+
+    if state.supports_transactions {
+        session.start_transaction(None).await?;
+    }
+
+    Ok(())
+}
+
+
+// A commit with an unknown outcome is safe to send again
+async fn commit_writes(state: &AppState, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+
+    if !state.supports_transactions {
+        return Ok(());
+    }
+
+    let mut attempt = 1;
+
+    loop {
+        match session.commit_transaction().await {
+            Err(e) if attempt < MAX_IMPORT_ATTEMPTS && e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) => {
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+
+async fn abort_writes(state: &AppState, session: &mut ClientSession) {
+
+    if state.supports_transactions {
+        let _ = session.abort_transaction().await;
+    }
+}
+
+
+// Next manual position of a list during an import, continuing
+// after what the list already holds
+async fn next_position(
+    docs_col: &mongodb::Collection<Document>,
+    session: &mut ClientSession,
+    last: &mut HashMap<Option<ObjectId>, String>,
+    list: Option<ObjectId>,
+    scope: &ListScope,
+) -> Result<String, String> {
+
+    let position = match last.get(&list) {
+        Some(previous) => key_between(Some(previous), None),
+        None => append_position_with_session(docs_col, scope, session).await?,
+    };
+
+    last.insert(list, position.clone());
+
+    Ok(position)
+}
+
+
+// List recent import jobs
+pub async fn list_import_jobs(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<ImportJobResponse>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .limit(20)
+        .projection(doc! { "errors": 0 })
+        .build();
+
+    let cursor = jobs_col
+        .find(doc! { "user_id": user_id }, options)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let jobs: Vec<ImportJob> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch import jobs".to_string()))?;
+
+    let response: Vec<ImportJobResponse> = jobs
+        .into_iter()
+        .map(ImportJobResponse::from)
+        .collect();
+
+    Ok(Json(response))
+}
+
+
+// Get status and counts of an import job
+pub async fn get_import_job(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<ImportJobResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let job_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid job id".to_string()))?;
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let job = jobs_col
+        .find_one(doc! { "_id": job_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Import job not found".to_string()))?;
+
+    Ok(Json(ImportJobResponse::from(job)))
+}
+
+
+// Cancel a previewed, pending or running import job
+pub async fn cancel_import_job(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<ImportJobResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let job_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid job id".to_string()))?;
+
+    let jobs_col = state.db.collection::<ImportJob>("import_jobs");
+
+    let now = Utc::now().to_rfc3339();
+
+    // The worker notices the status change on its next progress update
+    let result = jobs_col
+        .update_one(
+            doc! {
+                "_id": job_id,
+                "user_id": user_id,
+                "status": { "$in": ["preview", "pending", "running"] }
+            },
+            doc! { "$set": { "status": "cancelled", "updated_at": &now, "finished_at": &now } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to cancel import job".to_string()))?;
+
+    let job = jobs_col
+        .find_one(doc! { "_id": job_id, "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Import job not found".to_string()))?;
+
+    if result.matched_count == 0 {
+        return Err((StatusCode::CONFLICT, "Import job already finished".to_string()));
+    }
+
+    // Only a previewed job has stored entries
+    clear_items(&state, job_id).await;
+
+    Ok(Json(ImportJobResponse::from(job)))
+}
+
+
+// Jobs whose process stopped can never finish, mark them failed. Jobs of other
+// running processes keep a fresh heartbeat and are left alone
+pub async fn fail_interrupted_jobs(db: &mongodb::Database) {
+
+    let jobs_col = db.collection::<ImportJob>("import_jobs");
+
+    let now = Utc::now().to_rfc3339();
+
+    let stale = mongodb::bson::DateTime::from_millis(
+        mongodb::bson::DateTime::now().timestamp_millis() - HEARTBEAT_TIMEOUT.as_millis() as i64,
+    );
+
+    let result = jobs_col
+        .update_many(
+            doc! {
+                "status": { "$in": ["pending", "running"] },
+                "$or": [
+                    { "heartbeat_at": { "$lt": stale } },
+                    { "heartbeat_at": { "$exists": false } }
+                ]
+            },
+            doc! { "$set": {
+                "status": "failed",
+                "message": "Server stopped during import",
+                "updated_at": &now,
+                "finished_at": &now
+            }},
+            None,
+        )
+        .await;
+
+    if let Ok(result) = result {
+        if result.modified_count > 0 {
+            println!("Marked {} interrupted import jobs as failed", result.modified_count);
+        }
+    }
+}
+
+
+// Remove previews nobody committed or cancelled. Their entries are also removed
+// by the TTL index on import_items, this takes care of the job documents
+pub async fn expire_previews(db: &mongodb::Database) {
+
+    let jobs_col = db.collection::<ImportJob>("import_jobs");
+
+    // created_at is stored the way chrono serializes it
+    let cutoff = Utc::now() - chrono::Duration::from_std(PREVIEW_TTL).unwrap();
+    let Ok(cutoff) = mongodb::bson::to_bson(&cutoff) else {
+        return;
+    };
+
+    let filter = doc! { "status": "preview", "created_at": { "$lt": cutoff } };
+
+    let Ok(job_ids) = jobs_col.distinct("_id", filter.clone(), None).await else {
+        return;
+    };
+
+    if job_ids.is_empty() {
+        return;
+    }
+
+    let _ = db
+        .collection::<ImportItem>("import_items")
+        .delete_many(doc! { "job_id": { "$in": &job_ids } }, None)
+        .await;
+
+    let result = jobs_col
+        .delete_many(doc! { "_id": { "$in": &job_ids }, "status": "preview" }, None)
+        .await;
+
+    if let Ok(result) = result {
+        if result.deleted_count > 0 {
+            println!("Removed {} expired import previews", result.deleted_count);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A throwaway database on MONGO_URI with a running job to import into
+    async fn test_job(name: &str, transactions: bool) -> (AppState, ObjectId, ObjectId) {
+
+        let uri = std::env::var("MONGO_URI").expect("MONGO_URI missing");
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(name);
+        db.drop(None).await.unwrap();
+
+        let state = AppState {
+            supports_transactions: transactions,
+            db: db.clone(),
+            jwt_secret: String::new(),
+            max_upload_bytes: 0,
+            public_url: String::new(),
+            instance_id: "test".to_string(),
         };
 
-        // Create bookmark
+        let user_id = ObjectId::new();
         let now = Utc::now();
-        let bookmark = Bookmark {
+
+        // progress_update stops the import unless the job is running
+        let job = ImportJob {
             id: None,
             user_id,
-            title: parsed_bookmark.title,
-            url: parsed_bookmark.url,
-            description: None,
-            tags: vec![],
-            collection_id,
+            status: ImportStatus::Running,
+            total: 0,
+            processed: 0,
+            imported: 0,
+            skipped: 0,
+            updated: 0,
+            collections_created: 0,
+            errors: vec![],
+            message: None,
+            worker_id: None,
+            heartbeat_at: None,
             created_at: now,
             updated_at: now,
+            finished_at: None,
         };
 
-        bookmarks_col
-            .insert_one(&bookmark, None)
+        let job_id = db
+            .collection::<ImportJob>("import_jobs")
+            .insert_one(&job, None)
             .await
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save bookmark".to_string()))?;
+            .unwrap()
+            .inserted_id
+            .as_object_id()
+            .unwrap();
 
-        imported_count += 1;
+        (state, user_id, job_id)
     }
 
-    Ok(Json(serde_json::json!({
-        "message": "Import complete",
-        "imported": imported_count,
-        "skipped": skipped_count,
-        "collections_created": folder_map.len()
-    })))
-}
\ No newline at end of file
+    fn parsed(count: usize) -> Vec<ParsedBookmark> {
+        (0..count)
+            .map(|i| ParsedBookmark {
+                title: format!("Bookmark {}", i),
+                url: format!("https://example.com/{}", i),
+                folder_path: vec![format!("Folder {}", i % 20)],
+                tags: vec!["imported".to_string()],
+                keyword: None,
+                added_at: None,
+            })
+            .collect()
+    }
+
+    // Throughput of the batched writer against a real server, see "Import speed"
+    // in the Readme. Uses a throwaway database on MONGO_URI:
+    // MONGO_URI=mongodb://localhost:27017 cargo test --release import_throughput -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn import_throughput() {
+
+        let uri = std::env::var("MONGO_URI").expect("MONGO_URI missing");
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let transactions = crate::db::mongo::supports_transactions(&client.database("admin")).await;
+
+        let (state, user_id, job_id) = test_job("bookmark_import_bench", transactions).await;
+
+        let count = 10_000;
+        let items = parsed(count);
+
+        let mut progress = ImportProgress::default();
+        let started = std::time::Instant::now();
+
+        let result = import_parsed(&state, job_id, user_id, items, &ImportResolution::default(), &mut progress).await;
+
+        let elapsed = started.elapsed();
+        state.db.drop(None).await.unwrap();
+
+        assert_eq!(result, Ok(true));
+        assert_eq!(progress.imported, count as i64);
+
+        println!(
+            "{} bookmarks in {:.2?} ({:.0}/s, transactions: {})",
+            count,
+            elapsed,
+            count as f64 / elapsed.as_secs_f64(),
+            state.supports_transactions,
+        );
+    }
+
+    // An import failing in its last batch keeps nothing, with and without a
+    // transaction. Needs MONGO_URI like import_throughput:
+    // MONGO_URI=mongodb://localhost:27017 cargo test failed_import_keeps_nothing -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn failed_import_keeps_nothing() {
+
+        let uri = std::env::var("MONGO_URI").expect("MONGO_URI missing");
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let transactions = crate::db::mongo::supports_transactions(&client.database("admin")).await;
+
+        let modes = if transactions { vec![true, false] } else { vec![false] };
+
+        for transactions in modes {
+            let (state, user_id, job_id) = test_job("bookmark_import_rollback", transactions).await;
+
+            // The server rejects this URL, failing the batch it is in
+            let fail_url = "https://fail.example/";
+            let options = mongodb::options::CreateCollectionOptions::builder()
+                .validator(doc! { "url": { "$ne": fail_url } })
+                .build();
+            state.db.create_collection("bookmarks", options).await.unwrap();
+
+            let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+            let now = Utc::now();
+
+            // Overwritten by the first batch
+            let original = Bookmark {
+                id: Some(ObjectId::new()),
+                user_id,
+                title: "Original".to_string(),
+                url: "https://example.com/0".to_string(),
+                description: None,
+                tags: vec!["kept".to_string()],
+                collection_ids: vec![],
+                keyword: None,
+                positions: HashMap::new(),
+                created_at: now,
+                updated_at: now,
+            };
+            bookmarks_col.insert_one(&original, None).await.unwrap();
+
+            let mut items = parsed(BATCH_SIZE * 2 + 10);
+            items.last_mut().unwrap().url = fail_url.to_string();
+
+            let resolution = ImportResolution {
+                default: ConflictAction::Overwrite,
+                choices: HashMap::new(),
+            };
+
+            let mut progress = ImportProgress::default();
+            let result = import_parsed(&state, job_id, user_id, items, &resolution, &mut progress).await;
+
+            let bookmarks: Vec<Bookmark> = bookmarks_col
+                .find(doc! { "user_id": user_id }, None)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+
+            let collections = state.db
+                .collection::<Document>("collections")
+                .count_documents(doc! { "user_id": user_id }, None)
+                .await
+                .unwrap();
+
+            state.db.drop(None).await.unwrap();
+
+            assert!(result.is_err(), "transactions: {}", transactions);
+            assert_eq!(progress.imported, 0);
+            assert_eq!(progress.collections_created, 0);
+            assert_eq!(collections, 0, "transactions: {}", transactions);
+            assert_eq!(bookmarks.len(), 1, "transactions: {}", transactions);
+            assert_eq!(bookmarks[0].title, "Original");
+            assert_eq!(bookmarks[0].tags, vec!["kept"]);
+        }
+    }
+}
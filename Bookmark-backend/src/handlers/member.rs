@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{State, Path},
+    http::StatusCode,
+    Json,
+    Extension,
+};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use chrono::Utc;
+use validator::Validate;
+use futures::TryStreamExt;
+
+use crate::state::app_state::AppState;
+use crate::models::collection::Collection;
+use crate::models::bookmark::Bookmark;
+use crate::models::user::User;
+use crate::models::member::{
+    CollectionMember,
+    MemberRole,
+    MemberStatus,
+    InviteMemberRequest,
+    UpdateMemberRequest,
+    MemberResponse,
+    InvitationResponse,
+};
+use crate::utils::jwt::Claims;
+
+
+// Deepest collection tree walked when looking for ancestors or descendants
+const MAX_TREE_DEPTH: i32 = 64;
+
+
+// Role of the user on a collection, None when they have no access.
+// Membership of a collection also covers every collection below it
+pub async fn collection_role(
+    state: &AppState,
+    user_id: ObjectId,
+    collection_id: ObjectId,
+) -> Result<Option<(Collection, MemberRole)>, (StatusCode, String)> {
+
+    let mut roles = collection_roles(state, user_id, &[collection_id]).await?;
+
+    Ok(roles.remove(&collection_id))
+}
+
+
+// Role of the user on each of the given collections, left out where they have
+// no access. The ancestors of every collection come from one $graphLookup
+pub async fn collection_roles(
+    state: &AppState,
+    user_id: ObjectId,
+    collection_ids: &[ObjectId],
+) -> Result<HashMap<ObjectId, (Collection, MemberRole)>, (StatusCode, String)> {
+
+    let mut roles = HashMap::new();
+
+    if collection_ids.is_empty() {
+        return Ok(roles);
+    }
+
+    // Ancestors are owned by the same user as the collection, so the user's
+    // own collections never need theirs
+    let pipeline = vec![
+        doc! { "$match": { "_id": { "$in": collection_ids } } },
+        doc! { "$graphLookup": {
+            "from": "collections",
+            "startWith": "$parent_id",
+            "connectFromField": "parent_id",
+            "connectToField": "_id",
+            "as": "ancestors",
+            "maxDepth": MAX_TREE_DEPTH,
+            "restrictSearchWithMatch": { "user_id": { "$ne": user_id } }
+        } },
+        doc! { "$set": { "ancestors": "$ancestors._id" } },
+    ];
+
+    let cursor = state.db
+        .collection::<Collection>("collections")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let docs: Vec<Document> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+
+    // Shared collections with the collection itself and its ancestors
+    let mut shared: Vec<(Collection, Vec<ObjectId>)> = Vec::new();
+
+    for mut d in docs {
+        let ancestors: Vec<ObjectId> = match d.remove("ancestors") {
+            Some(Bson::Array(ids)) => ids.iter().filter_map(|id| id.as_object_id()).collect(),
+            _ => vec![],
+        };
+
+        let col: Collection = mongodb::bson::from_document(d)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+
+        if col.user_id == user_id {
+            roles.insert(col.id.unwrap(), (col, MemberRole::Owner));
+        } else {
+            let mut chain = vec![col.id.unwrap()];
+            chain.extend(ancestors);
+            shared.push((col, chain));
+        }
+    }
+
+    if shared.is_empty() {
+        return Ok(roles);
+    }
+
+    let chain_ids: Vec<ObjectId> = shared.iter().flat_map(|(_, chain)| chain.iter().copied()).collect();
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let cursor = members_col
+        .find(
+            doc! {
+                "collection_id": { "$in": chain_ids },
+                "user_id": user_id,
+                "status": "accepted"
+            },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let memberships: Vec<CollectionMember> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch members".to_string()))?;
+
+    for (col, chain) in shared {
+        let role = memberships
+            .iter()
+            .filter(|m| chain.contains(&m.collection_id))
+            .map(|m| m.role)
+            .max();
+
+        if let Some(role) = role {
+            roles.insert(col.id.unwrap(), (col, role));
+        }
+    }
+
+    Ok(roles)
+}
+
+
+// Collection the user can access with at least the given role
+pub async fn require_role(
+    state: &AppState,
+    user_id: ObjectId,
+    collection_id: ObjectId,
+    min_role: MemberRole,
+) -> Result<(Collection, MemberRole), (StatusCode, String)> {
+
+    let (col, role) = collection_role(state, user_id, collection_id)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    if role < min_role {
+        return Err((StatusCode::FORBIDDEN, "Insufficient permissions".to_string()));
+    }
+
+    Ok((col, role))
+}
+
+
+// Bookmark the user owns, or can reach through a shared collection
+pub async fn bookmark_access(
+    state: &AppState,
+    user_id: ObjectId,
+    bookmark_id: ObjectId,
+    min_role: MemberRole,
+) -> Result<Bookmark, (StatusCode, String)> {
+
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let not_found = (StatusCode::NOT_FOUND, "Bookmark not found".to_string());
+
+    let bookmark = bookmarks_col
+        .find_one(doc! { "_id": bookmark_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or(not_found.clone())?;
+
+    if bookmark.user_id == user_id {
+        return Ok(bookmark);
+    }
+
+    // The best role over all the collections the bookmark is in
+    let best = collection_roles(state, user_id, &bookmark.collection_ids)
+        .await?
+        .into_values()
+        .map(|(_, role)| role)
+        .max();
+
+    match best {
+        Some(role) if role >= min_role => Ok(bookmark),
+        Some(_) => Err((StatusCode::FORBIDDEN, "Insufficient permissions".to_string())),
+        None => Err(not_found),
+    }
+}
+
+
+// Collections shared with the user through an accepted invitation
+pub async fn accepted_memberships(
+    state: &AppState,
+    user_id: ObjectId,
+) -> Result<Vec<CollectionMember>, (StatusCode, String)> {
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let cursor = members_col
+        .find(doc! { "user_id": user_id, "status": "accepted" }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch members".to_string()))
+}
+
+
+// Collections shared with the user and everything below them, with the best
+// role the user has on each. The subtrees come from one $graphLookup
+pub async fn shared_collections(
+    state: &AppState,
+    user_id: ObjectId,
+) -> Result<HashMap<ObjectId, (Collection, MemberRole)>, (StatusCode, String)> {
+
+    let memberships = accepted_memberships(state, user_id).await?;
+
+    let mut shared = HashMap::new();
+
+    if memberships.is_empty() {
+        return Ok(shared);
+    }
+
+    let root_ids: Vec<ObjectId> = memberships.iter().map(|m| m.collection_id).collect();
+
+    let pipeline = vec![
+        doc! { "$match": { "_id": { "$in": &root_ids } } },
+        doc! { "$graphLookup": {
+            "from": "collections",
+            "startWith": "$_id",
+            "connectFromField": "_id",
+            "connectToField": "parent_id",
+            "as": "descendants",
+            "maxDepth": MAX_TREE_DEPTH
+        } },
+    ];
+
+    let cursor = state.db
+        .collection::<Collection>("collections")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let docs: Vec<Document> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+
+    let invalid = || (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string());
+
+    for mut d in docs {
+        let descendants = match d.remove("descendants") {
+            Some(Bson::Array(descendants)) => descendants,
+            _ => vec![],
+        };
+
+        let root: Collection = mongodb::bson::from_document(d).map_err(|_| invalid())?;
+
+        let role = memberships
+            .iter()
+            .filter(|m| Some(m.collection_id) == root.id)
+            .map(|m| m.role)
+            .max()
+            .unwrap_or(MemberRole::Viewer);
+
+        let mut subtree = vec![root];
+        for descendant in descendants {
+            let Bson::Document(descendant) = descendant else {
+                continue;
+            };
+            subtree.push(mongodb::bson::from_document(descendant).map_err(|_| invalid())?);
+        }
+
+        // A collection below two shared roots gets the better of both roles
+        for col in subtree {
+            let entry = shared.entry(col.id.unwrap()).or_insert((col, role));
+            entry.1 = entry.1.max(role);
+        }
+    }
+
+    Ok(shared)
+}
+
+
+// Only the owner can hand out or take away the admin role
+fn check_role_change(caller: MemberRole, from: Option<MemberRole>, to: MemberRole) -> Result<(), (StatusCode, String)> {
+
+    if to == MemberRole::Owner {
+        return Err((StatusCode::BAD_REQUEST, "Ownership cannot be assigned".to_string()));
+    }
+
+    let touches_admin = to == MemberRole::Admin || from == Some(MemberRole::Admin);
+
+    if touches_admin && caller != MemberRole::Owner {
+        return Err((StatusCode::FORBIDDEN, "Only the owner can manage admins".to_string()));
+    }
+
+    Ok(())
+}
+
+
+// List members of a collection, owner first
+pub async fn list_members(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<MemberResponse>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let (col, _) = require_role(&state, user_id, collection_id, MemberRole::Viewer).await?;
+
+    let users_col = state.db.collection::<User>("users");
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let owner = users_col
+        .find_one(doc! { "_id": col.user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Owner not found".to_string()))?;
+
+    let cursor = members_col
+        .find(doc! { "collection_id": collection_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let members: Vec<CollectionMember> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch members".to_string()))?;
+
+    let mut response = vec![MemberResponse {
+        id: None,
+        user_id: col.user_id.to_hex(),
+        email: owner.email,
+        role: MemberRole::Owner,
+        status: MemberStatus::Accepted,
+        created_at: col.created_at,
+    }];
+
+    response.extend(members.into_iter().map(MemberResponse::from));
+
+    Ok(Json(response))
+}
+
+
+// Invite a registered user to a collection. The answer is the same whether or
+// not the email belongs to a user, so it can't be used to look up accounts
+pub async fn invite_member(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(body): Json<InviteMemberRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let (col, caller_role) = require_role(&state, user_id, collection_id, MemberRole::Admin).await?;
+
+    check_role_change(caller_role, None, body.role)?;
+
+    let users_col = state.db.collection::<User>("users");
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let invitee = users_col
+        .find_one(doc! { "email": &body.email }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let Some(invitee) = invitee else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+
+    let invitee_id = invitee.id.unwrap();
+
+    if invitee_id == col.user_id {
+        return Err((StatusCode::BAD_REQUEST, "User already owns this collection".to_string()));
+    }
+
+    let existing = members_col
+        .find_one(doc! { "collection_id": collection_id, "user_id": invitee_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    if existing.is_some() {
+        return Err((StatusCode::CONFLICT, "User is already a member".to_string()));
+    }
+
+    let now = Utc::now();
+
+    let member = CollectionMember {
+        id: None,
+        collection_id,
+        user_id: invitee_id,
+        email: invitee.email,
+        role: body.role,
+        status: MemberStatus::Pending,
+        invited_by: user_id,
+        created_at: now,
+        updated_at: now,
+    };
+
+    members_col
+        .insert_one(&member, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to invite member".to_string()))?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+
+// Change the role of a member
+pub async fn update_member(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((id, member_id)): Path<(String, String)>,
+    Json(body): Json<UpdateMemberRequest>,
+) -> Result<Json<MemberResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let member_id = ObjectId::parse_str(&member_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid member id".to_string()))?;
+
+    let (_, caller_role) = require_role(&state, user_id, collection_id, MemberRole::Admin).await?;
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let member = members_col
+        .find_one(doc! { "_id": member_id, "collection_id": collection_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Member not found".to_string()))?;
+
+    check_role_change(caller_role, Some(member.role), body.role)?;
+
+    let role = mongodb::bson::to_bson(&body.role)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update member".to_string()))?;
+
+    members_col
+        .update_one(
+            doc! { "_id": member_id },
+            doc! { "$set": { "role": role, "updated_at": Utc::now().to_rfc3339() } },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update member".to_string()))?;
+
+    let mut updated = member;
+    updated.role = body.role;
+
+    Ok(Json(MemberResponse::from(updated)))
+}
+
+
+// Remove a member, members can always remove themselves
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((id, member_id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let collection_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+
+    let member_id = ObjectId::parse_str(&member_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid member id".to_string()))?;
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let member = members_col
+        .find_one(doc! { "_id": member_id, "collection_id": collection_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Member not found".to_string()))?;
+
+    if member.user_id != user_id {
+        let (_, caller_role) = require_role(&state, user_id, collection_id, MemberRole::Admin).await?;
+
+        if member.role == MemberRole::Admin && caller_role != MemberRole::Owner {
+            return Err((StatusCode::FORBIDDEN, "Only the owner can manage admins".to_string()));
+        }
+    }
+
+    members_col
+        .delete_one(doc! { "_id": member_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove member".to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+// Pending invitations for the user
+pub async fn list_invitations(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<InvitationResponse>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+    let collection = state.db.collection::<Collection>("collections");
+
+    let cursor = members_col
+        .find(doc! { "user_id": user_id, "status": "pending" }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let invitations: Vec<CollectionMember> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch invitations".to_string()))?;
+
+    let collection_ids: Vec<ObjectId> = invitations.iter().map(|i| i.collection_id).collect();
+
+    let cursor = collection
+        .find(doc! { "_id": { "$in": &collection_ids } }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let collections: Vec<Collection> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?;
+
+    // Invitations to collections deleted since are left out
+    let response = invitations
+        .into_iter()
+        .filter_map(|i| {
+            let col = collections.iter().find(|c| c.id == Some(i.collection_id))?;
+            Some(InvitationResponse {
+                id: i.id.unwrap().to_hex(),
+                collection_id: i.collection_id.to_hex(),
+                collection_name: col.name.clone(),
+                role: i.role,
+                invited_by: i.invited_by.to_hex(),
+                created_at: i.created_at,
+            })
+        })
+        .collect();
+
+    Ok(Json(response))
+}
+
+
+// Accept an invitation
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<MemberResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let invitation_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid invitation id".to_string()))?;
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let member = members_col
+        .find_one_and_update(
+            doc! { "_id": invitation_id, "user_id": user_id, "status": "pending" },
+            doc! { "$set": { "status": "accepted", "updated_at": Utc::now().to_rfc3339() } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to accept invitation".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Invitation not found".to_string()))?;
+
+    Ok(Json(MemberResponse::from(member)))
+}
+
+
+// Decline an invitation
+pub async fn decline_invitation(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let invitation_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid invitation id".to_string()))?;
+
+    let members_col = state.db.collection::<CollectionMember>("collection_members");
+
+    let result = members_col
+        .delete_one(doc! { "_id": invitation_id, "user_id": user_id, "status": "pending" }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to decline invitation".to_string()))?;
+
+    if result.deleted_count == 0 {
+        return Err((StatusCode::NOT_FOUND, "Invitation not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,287 @@
+use axum::{
+    extract::{State, Path, Query, Form},
+    http::{StatusCode, HeaderMap, header},
+    response::{IntoResponse, Response, Html},
+    Json,
+};
+use mongodb::bson::{doc, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use futures::TryStreamExt;
+use bcrypt::verify;
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::state::app_state::AppState;
+use crate::models::collection::{Collection, PublicBookmark, PublicCollection};
+use crate::models::bookmark::Bookmark;
+use crate::utils::escape;
+use crate::utils::filter::collection_bookmark_filter;
+
+// Header API clients send the password of a protected link in
+const PASSWORD_HEADER: &str = "x-share-password";
+
+// Wrong passwords a link accepts per window, after that it is locked until the
+// window ends. Counted per link in the database, so every server process agrees
+const MAX_PASSWORD_FAILURES: i64 = 10;
+const PASSWORD_WINDOW_SECS: i64 = 15 * 60;
+
+
+#[derive(Debug, Deserialize)]
+pub struct PublicQuery {
+    // json or html, otherwise picked from the Accept header
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockForm {
+    pub password: String,
+}
+
+
+// Public page of a shared collection, no login needed
+pub async fn public_collection(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Query(query): Query<PublicQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+
+    let password = headers
+        .get(PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    render_public(&state, &slug, wants_html(&query, &headers), password).await
+}
+
+
+// Password form submission of the HTML page
+pub async fn unlock_public_collection(
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, (StatusCode, String)> {
+
+    render_public(&state, &slug, true, Some(form.password)).await
+}
+
+
+fn wants_html(query: &PublicQuery, headers: &HeaderMap) -> bool {
+
+    match query.format.as_deref() {
+        Some("html") => true,
+        Some(_) => false,
+        None => headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html")),
+    }
+}
+
+
+async fn render_public(
+    state: &AppState,
+    slug: &str,
+    html: bool,
+    password: Option<String>,
+) -> Result<Response, (StatusCode, String)> {
+
+    let collection = state.db.collection::<Collection>("collections");
+    let bookmarks_col = state.db.collection::<Bookmark>("bookmarks");
+
+    let col = collection
+        .find_one(doc! { "share.slug": slug }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Collection not found".to_string()))?;
+
+    let share = col.share.clone().unwrap();
+
+    if share.expires_at.is_some_and(|e| e <= Utc::now()) {
+        return Err((StatusCode::GONE, "This link has expired".to_string()));
+    }
+
+    if let Some(password_hash) = share.password_hash.clone() {
+        let window = Utc::now().timestamp() / PASSWORD_WINDOW_SECS;
+
+        if password.is_some() && password_failures(state, slug, window).await? >= MAX_PASSWORD_FAILURES {
+            let error = "Too many wrong passwords, try again later";
+            let retry_after = ((window + 1) * PASSWORD_WINDOW_SECS - Utc::now().timestamp()).to_string();
+            let headers = [(header::RETRY_AFTER, retry_after), (header::VARY, "Accept".to_string())];
+
+            if html {
+                return Ok((StatusCode::TOO_MANY_REQUESTS, headers, Html(password_page(slug, &col.name, error))).into_response());
+            }
+            return Ok((StatusCode::TOO_MANY_REQUESTS, headers, error).into_response());
+        }
+
+        // bcrypt is slow on purpose, keep it off the runtime
+        let unlocked = match password.clone() {
+            Some(p) => tokio::task::spawn_blocking(move || verify(p, &password_hash).unwrap_or(false))
+                .await
+                .unwrap_or(false),
+            None => false,
+        };
+
+        if !unlocked {
+            let error = if password.is_some() { "Invalid password" } else { "Password required" };
+
+            if password.is_some() {
+                record_password_failure(state, slug, window).await?;
+            }
+
+            if html {
+                return Ok((StatusCode::UNAUTHORIZED, [(header::VARY, "Accept")], Html(password_page(slug, &col.name, error))).into_response());
+            }
+            return Ok((StatusCode::UNAUTHORIZED, [(header::VARY, "Accept")], error).into_response());
+        }
+    }
+
+    let cursor = bookmarks_col
+        .find(
+            collection_bookmark_filter(&col)?,
+            FindOptions::builder().sort(doc! { "created_at": -1 }).build(),
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let bookmarks: Vec<Bookmark> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bookmarks".to_string()))?;
+
+    let public = PublicCollection {
+        name: col.name,
+        description: col.description,
+        bookmarks: bookmarks
+            .into_iter()
+            .map(|b| PublicBookmark {
+                title: b.title,
+                url: b.url,
+                description: b.description,
+                tags: b.tags,
+                created_at: b.created_at,
+            })
+            .collect(),
+        updated_at: col.updated_at,
+    };
+
+    // Protected pages must not end up in shared caches. The same URL serves
+    // HTML or JSON, so caches have to key on Accept
+    let cache = if share.password_hash.is_some() { "private, no-store" } else { "public, max-age=60" };
+    let headers = [(header::CACHE_CONTROL, cache), (header::VARY, "Accept")];
+
+    if html {
+        Ok((headers, Html(collection_page(&public))).into_response())
+    } else {
+        Ok((headers, Json(public)).into_response())
+    }
+}
+
+
+// Wrong passwords sent for a link in the current window
+async fn password_failures(state: &AppState, slug: &str, window: i64) -> Result<i64, (StatusCode, String)> {
+
+    let attempts = state.db
+        .collection::<Document>("share_attempts")
+        .find_one(doc! { "slug": slug, "window": window }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    Ok(attempts.and_then(|a| a.get_i64("failures").ok()).unwrap_or(0))
+}
+
+
+async fn record_password_failure(state: &AppState, slug: &str, window: i64) -> Result<(), (StatusCode, String)> {
+
+    // Removed by a TTL index once the window is over
+    let expires_at = mongodb::bson::DateTime::from_millis((window + 1) * PASSWORD_WINDOW_SECS * 1000);
+
+    state.db
+        .collection::<Document>("share_attempts")
+        .update_one(
+            doc! { "slug": slug, "window": window },
+            doc! {
+                "$inc": { "failures": 1_i64 },
+                "$setOnInsert": { "expires_at": expires_at }
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    Ok(())
+}
+
+
+const PAGE_STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:720px;margin:2rem auto;padding:0 1rem;color:#222}\
+li{margin:.75rem 0}small{color:#666}.tag{background:#eee;border-radius:4px;padding:0 .3rem;margin-right:.3rem}";
+
+
+fn collection_page(collection: &PublicCollection) -> String {
+
+    let mut items = String::new();
+
+    for b in &collection.bookmarks {
+        // Only web links are clickable, a javascript: or data: URL stays text
+        let linkable = url::Url::parse(&b.url).is_ok_and(|u| matches!(u.scheme(), "http" | "https"));
+
+        if linkable {
+            items.push_str(&format!(
+                "<li><a href=\"{}\" rel=\"nofollow noopener\">{}</a>",
+                escape::xml(&b.url),
+                escape::xml(&b.title),
+            ));
+        } else {
+            items.push_str(&format!(
+                "<li>{}<br><small>{}</small>",
+                escape::xml(&b.title),
+                escape::xml(&b.url),
+            ));
+        }
+        if let Some(description) = b.description.as_deref().filter(|d| !d.is_empty()) {
+            items.push_str(&format!("<br><small>{}</small>", escape::xml(description)));
+        }
+        if !b.tags.is_empty() {
+            items.push_str("<br>");
+            for tag in &b.tags {
+                items.push_str(&format!("<span class=\"tag\">{}</span>", escape::xml(tag)));
+            }
+        }
+        items.push_str("</li>\n");
+    }
+
+    let description = collection.description
+        .as_deref()
+        .map(|d| format!("<p>{}</p>\n", escape::xml(d)))
+        .unwrap_or_default();
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+<title>{name}</title>\n<style>{style}</style>\n</head>\n<body>\n\
+<h1>{name}</h1>\n{description}<ul>\n{items}</ul>\n</body>\n</html>\n",
+        name = escape::xml(&collection.name),
+        style = PAGE_STYLE,
+        description = description,
+        items = items,
+    )
+}
+
+
+fn password_page(slug: &str, name: &str, error: &str) -> String {
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+<title>{name}</title>\n<style>{style}</style>\n</head>\n<body>\n\
+<h1>{name}</h1>\n<p>{error}</p>\n\
+<form method=\"post\" action=\"/p/{slug}\">\n\
+<input type=\"password\" name=\"password\" autofocus>\n<button type=\"submit\">Open</button>\n\
+</form>\n</body>\n</html>\n",
+        name = escape::xml(name),
+        style = PAGE_STYLE,
+        error = escape::xml(error),
+        slug = escape::xml(slug),
+    )
+}
@@ -4,26 +4,77 @@ use axum::{
     Json,
     Extension,
 };
-use mongodb::bson::{doc, oid::ObjectId};
+use std::collections::HashMap;
+
+use mongodb::bson::{doc, oid::ObjectId, to_bson, Bson, Document};
+use mongodb::options::FindOptions;
 use futures::TryStreamExt;
 use serde::Deserialize;
+use chrono::{DateTime, NaiveDate, Utc};
 
 use crate::state::app_state::AppState;
 use crate::models::bookmark::{Bookmark, BookmarkResponse};
+use crate::models::collection::Collection;
+use crate::models::search::{SearchResponse, SearchResult, SearchHighlights, SearchFacets, CollectionFacet};
+use crate::models::tag::TagSuggestion;
+use crate::handlers::collection::count_field;
 use crate::utils::jwt::Claims;
+use crate::utils::filter::{TagMatch, tags_filter, url_host, host_match};
+use crate::utils::tags::{load_tag_settings, parse_tag_terms};
+use crate::utils::highlight::{search_terms, highlight};
+
+const DEFAULT_PER_PAGE: u64 = 20;
+const MAX_PER_PAGE: u64 = 100;
+
+// Tags and collections listed in facets
+const MAX_FACETS: i32 = 20;
+
+// Longest description snippet in highlights, in characters
+const SNIPPET_CHARS: usize = 160;
 
 
 #[derive(Debug, Deserialize)]
 pub struct SearchQuery {
     pub q: String,
+
+    // Starts at 1
+    pub page: Option<u64>,
+
+    pub per_page: Option<u64>,
+
+    pub collection_id: Option<String>,
+
+    // Same as the tag filter, e.g. "rust,-archived"
+    pub tags: Option<String>,
+
+    #[serde(default, rename = "match")]
+    pub match_mode: TagMatch,
+
+    // A host like "github.com" or a URL, "www." is ignored
+    pub domain: Option<String>,
+
+    // Creation date range, RFC 3339 or YYYY-MM-DD, both ends included
+    pub from: Option<String>,
+
+    pub to: Option<String>,
+}
+
+
+// Results before the page, None when a huge page number would overflow
+// or pass the largest skip the server takes
+fn page_skip(page: u64, per_page: u64) -> Option<u64> {
+    page.saturating_sub(1)
+        .checked_mul(per_page)
+        .filter(|skip| *skip <= i64::MAX as u64)
 }
 
 
+// Search bookmarks, most relevant first. Title matches weigh most, see create_indexes
 pub async fn search_bookmarks(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Query(query): Query<SearchQuery>,
-) -> Result<Json<Vec<BookmarkResponse>>, (StatusCode, String)> {
+) -> Result<Json<SearchResponse>, (StatusCode, String)> {
 
     if query.q.trim().is_empty() {
         return Err((StatusCode::BAD_REQUEST, "Search query required".to_string()));
@@ -32,29 +83,227 @@ pub async fn search_bookmarks(
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
-    let collection = state.db.collection::<Bookmark>("bookmarks");
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
 
-    let filter = doc! {
-        "$and": [
-            { "user_id": user_id },
-            { "$text": { "$search": &query.q } }
-        ]
-    };
+    let skip = page_skip(page, per_page)
+        .ok_or((StatusCode::BAD_REQUEST, "Page out of range".to_string()))?;
+
+    let collection = state.db.collection::<Document>("bookmarks");
+
+    let filter = search_filter(&state, user_id, &query).await?;
+
+    let filter_for_facets = filter.clone();
+
+    let total = collection
+        .count_documents(filter.clone(), None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let options = FindOptions::builder()
+        .projection(doc! { "score": { "$meta": "textScore" } })
+        .sort(doc! { "score": { "$meta": "textScore" }, "created_at": -1 })
+        .skip(skip)
+        .limit(per_page as i64)
+        .build();
 
     let cursor = collection
-        .find(filter, None)
+        .find(filter, options)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
 
-    let bookmarks: Vec<Bookmark> = cursor
+    let documents: Vec<Document> = cursor
         .try_collect()
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch results".to_string()))?;
 
-    let response: Vec<BookmarkResponse> = bookmarks
+    let terms = search_terms(&query.q);
+
+    let results = documents
+        .into_iter()
+        .map(|d| {
+            let score = d.get_f64("score").unwrap_or(0.0);
+            let bookmark: Bookmark = mongodb::bson::from_document(d)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch results".to_string()))?;
+
+            let highlights = SearchHighlights {
+                title: highlight(&bookmark.title, &terms, None),
+                description: bookmark.description
+                    .as_deref()
+                    .and_then(|d| highlight(d, &terms, Some(SNIPPET_CHARS))),
+            };
+
+            Ok(SearchResult { bookmark: BookmarkResponse::from(bookmark), score, highlights })
+        })
+        .collect::<Result<Vec<_>, (StatusCode, String)>>()?;
+
+    let facets = search_facets(&state, filter_for_facets).await?;
+
+    Ok(Json(SearchResponse { results, total, page, per_page, facets }))
+}
+
+
+// The text query combined with the other filters of the search
+async fn search_filter(
+    state: &AppState,
+    user_id: ObjectId,
+    query: &SearchQuery,
+) -> Result<Document, (StatusCode, String)> {
+
+    let mut filters = vec![
+        doc! { "user_id": user_id },
+        doc! { "$text": { "$search": &query.q } },
+    ];
+
+    if let Some(collection_id) = &query.collection_id {
+        let collection_id = ObjectId::parse_str(collection_id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+        filters.push(doc! { "collection_ids": collection_id });
+    }
+
+    if let Some(tags) = &query.tags {
+        let settings = load_tag_settings(&state.db, user_id).await?;
+        let (included, excluded) = parse_tag_terms(tags, &settings);
+        let tags = tags_filter(&included, &excluded, query.match_mode);
+        if !tags.is_empty() {
+            filters.push(tags);
+        }
+    }
+
+    if let Some(domain) = query.domain.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        // A bare host is not a URL, give it a scheme to parse it the same way
+        let host = url_host(domain)
+            .or_else(|| url_host(&format!("http://{}", domain)))
+            .ok_or((StatusCode::BAD_REQUEST, "Invalid domain".to_string()))?;
+        filters.push(doc! { "url": host_match(&host) });
+    }
+
+    let mut created = Document::new();
+    if let Some(from) = &query.from {
+        created.insert("$gte", date_bound(from, false)?);
+    }
+    if let Some(to) = &query.to {
+        let (op, bound) = match NaiveDate::parse_from_str(to, "%Y-%m-%d") {
+            // The whole last day
+            Ok(_) => ("$lt", date_bound(to, true)?),
+            Err(_) => ("$lte", date_bound(to, false)?),
+        };
+        created.insert(op, bound);
+    }
+    if !created.is_empty() {
+        filters.push(doc! { "created_at": created });
+    }
+
+    Ok(doc! { "$and": filters })
+}
+
+
+// A date of the range, serialized like stored created_at values so the
+// strings compare in time order. A plain date is its start, or with
+// next_day the start of the day after
+fn date_bound(value: &str, next_day: bool) -> Result<Bson, (StatusCode, String)> {
+
+    let invalid = || (StatusCode::BAD_REQUEST, format!("Invalid date: {}", value));
+
+    let date = match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(day) => {
+            let day = if next_day { day.succ_opt().ok_or_else(invalid)? } else { day };
+            day.and_hms_opt(0, 0, 0).ok_or_else(invalid)?.and_utc()
+        }
+        Err(_) => DateTime::parse_from_rfc3339(value).map_err(|_| invalid())?.with_timezone(&Utc),
+    };
+
+    to_bson(&date).map_err(|_| invalid())
+}
+
+
+// Result counts by tag and by collection over all matches, not only the page
+async fn search_facets(state: &AppState, filter: Document) -> Result<SearchFacets, (StatusCode, String)> {
+
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$facet": {
+            "tags": [
+                { "$unwind": "$tags" },
+                { "$group": { "_id": "$tags", "count": { "$sum": 1 } } },
+                { "$sort": { "count": -1, "_id": 1 } },
+                { "$limit": MAX_FACETS },
+            ],
+            "collections": [
+                { "$unwind": "$collection_ids" },
+                { "$group": { "_id": "$collection_ids", "count": { "$sum": 1 } } },
+                { "$sort": { "count": -1, "_id": 1 } },
+                { "$limit": MAX_FACETS },
+            ],
+        } },
+    ];
+
+    let facets = state.db.collection::<Bookmark>("bookmarks")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .try_next()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to count results".to_string()))?
+        .unwrap_or_default();
+
+    let buckets = |name: &str| -> Vec<Document> {
+        facets
+            .get_array(name)
+            .map(|a| a.iter().filter_map(|b| b.as_document().cloned()).collect())
+            .unwrap_or_default()
+    };
+
+    let tags = buckets("tags")
+        .iter()
+        .map(|b| Ok(TagSuggestion {
+            name: b.get_str("_id").unwrap_or_default().to_string(),
+            count: count_field(b)?,
+        }))
+        .collect::<Result<Vec<_>, (StatusCode, String)>>()?;
+
+    let collection_buckets = buckets("collections");
+    let ids: Vec<ObjectId> = collection_buckets.iter().filter_map(|b| b.get_object_id("_id").ok()).collect();
+
+    let names: HashMap<ObjectId, String> = state.db.collection::<Collection>("collections")
+        .find(doc! { "_id": { "$in": &ids } }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .try_collect::<Vec<Collection>>()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch collections".to_string()))?
         .into_iter()
-        .map(BookmarkResponse::from)
+        .filter_map(|c| c.id.map(|id| (id, c.name)))
         .collect();
 
-    Ok(Json(response))
-}
\ No newline at end of file
+    // Collections that no longer exist are left out
+    let collections = collection_buckets
+        .iter()
+        .filter_map(|b| {
+            let id = b.get_object_id("_id").ok()?;
+            let name = names.get(&id)?.clone();
+            Some(count_field(b).map(|count| CollectionFacet { id: id.to_hex(), name, count }))
+        })
+        .collect::<Result<Vec<_>, (StatusCode, String)>>()?;
+
+    Ok(SearchFacets { tags, collections })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_skip_counts_earlier_pages() {
+        assert_eq!(page_skip(1, 20), Some(0));
+        assert_eq!(page_skip(3, 20), Some(40));
+    }
+
+    #[test]
+    fn page_skip_rejects_overflowing_pages() {
+        assert_eq!(page_skip(u64::MAX, MAX_PER_PAGE), None);
+        assert_eq!(page_skip(i64::MAX as u64, 2), None);
+        assert_eq!(page_skip(i64::MAX as u64 / 100 + 1, 100), Some(i64::MAX as u64 / 100 * 100));
+    }
+}
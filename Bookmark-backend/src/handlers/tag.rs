@@ -1,43 +1,113 @@
+use std::collections::{HashMap, HashSet};
+
 use axum::{
-    extract::{State, Query},
+    extract::{State, Path, Query},
     http::StatusCode,
     Json,
     Extension,
 };
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::UpdateOptions;
 use futures::TryStreamExt;
+use chrono::Utc;
 use serde::Deserialize;
+use validator::Validate;
 
 use crate::state::app_state::AppState;
 use crate::models::bookmark::{Bookmark, BookmarkResponse};
+use crate::models::feed::Feed;
+use crate::models::tag::{
+    RenameTagRequest,
+    MergeTagsRequest,
+    TagChangeResponse,
+    TagSettings,
+    UpdateTagSettingsRequest,
+    TagSettingsResponse,
+    TagNode,
+    TagSuggestion,
+    Tag,
+    CreateTagRequest,
+    UpdateTagRequest,
+    TagResponse,
+};
+use crate::models::member::MemberRole;
+use crate::handlers::member::bookmark_access;
+use crate::handlers::collection::count_field;
 use crate::utils::jwt::Claims;
+use crate::utils::filter::{TagMatch, tag_match, tags_filter, escape_regex, url_host, host_match, domain_label};
+use crate::utils::tags::{
+    clean_tag,
+    normalize_tag,
+    parse_tag_terms,
+    load_tag_settings,
+    normalize_stored_tags,
+    register_tags,
+    rename_tag_metadata,
+};
 
 
 #[derive(Debug, Deserialize)]
 pub struct TagQuery {
+    // A single tag
+    pub tag: Option<String>,
+
+    // Comma separated, tags starting with - are excluded, e.g. "rust,async,-archived"
+    pub tags: Option<String>,
+
+    #[serde(default, rename = "match")]
+    pub match_mode: TagMatch,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestQuery {
+    // Start of a tag or of one of its / levels, empty for the most used tags
+    #[serde(default)]
+    pub prefix: String,
+
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RelatedQuery {
     pub tag: String,
+
+    pub limit: Option<i64>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SuggestionsQuery {
+    pub limit: Option<i64>,
+}
+
+// Facets listed for a tag filter
+const MAX_FACETS: i64 = 100;
+
+const DEFAULT_SUGGESTIONS: i64 = 10;
+const MAX_SUGGESTIONS: i64 = 50;
+
+fn suggestion_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS)
+}
 
-// List all unique tags with counts
+
+// List tags as a tree of their / levels, with counts
 pub async fn list_tags(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, String)> {
+) -> Result<Json<Vec<TagNode>>, (StatusCode, String)> {
 
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
+    // Bookmarks with the same tags are counted together
     let pipeline = vec![
-        doc! { "$match": { "user_id": user_id } },
-        doc! { "$unwind": "$tags" },
+        doc! { "$match": { "user_id": user_id, "tags.0": { "$exists": true } } },
         doc! { "$group": {
             "_id": "$tags",
             "count": { "$sum": 1 }
         }},
-        doc! { "$sort": { "count": -1 } },
     ];
 
     let cursor = collection
@@ -45,26 +115,91 @@ pub async fn list_tags(
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
 
-    let results: Vec<mongodb::bson::Document> = cursor
+    let results: Vec<Document> = cursor
         .try_collect()
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tags".to_string()))?;
 
-    let tags: Vec<serde_json::Value> = results
-        .into_iter()
-        .map(|doc| {
-            serde_json::json!({
-                "name": doc.get_str("_id").unwrap_or(""),
-                "count": doc.get_i32("count").unwrap_or(0)
-            })
+    // Path to (count with descendants, own count)
+    let mut counts: HashMap<String, (u64, u64)> = HashMap::new();
+
+    for result in &results {
+        let count = count_field(result)?;
+        let tags = result.get_array("_id").map(|t| t.as_slice()).unwrap_or_default();
+
+        let mut paths: HashSet<&str> = HashSet::new();
+
+        for tag in tags.iter().filter_map(|t| t.as_str()) {
+            counts.entry(tag.to_string()).or_default().1 += count;
+
+            paths.insert(tag);
+            paths.extend(tag.match_indices('/').map(|(i, _)| &tag[..i]));
+        }
+
+        for path in paths {
+            counts.entry(path.to_string()).or_default().0 += count;
+        }
+    }
+
+    // Tags with metadata are listed even without bookmarks
+    let metadata: Vec<Tag> = state.db.collection::<Tag>("tags")
+        .find(doc! { "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tags".to_string()))?;
+
+    for tag in &metadata {
+        counts.entry(tag.name.clone()).or_default();
+        for (i, _) in tag.name.match_indices('/') {
+            counts.entry(tag.name[..i].to_string()).or_default();
+        }
+    }
+
+    let metadata: HashMap<String, Tag> = metadata.into_iter().map(|t| (t.name.clone(), t)).collect();
+
+    Ok(Json(tag_tree(&counts, &metadata, None)))
+}
+
+
+// Nodes right below parent, None for the top level, pinned then most used first
+fn tag_tree(
+    counts: &HashMap<String, (u64, u64)>,
+    metadata: &HashMap<String, Tag>,
+    parent: Option<&str>,
+) -> Vec<TagNode> {
+
+    let mut nodes: Vec<TagNode> = counts
+        .iter()
+        .filter(|(path, _)| path.rsplit_once('/').map(|(p, _)| p) == parent)
+        .map(|(path, (count, own_count))| {
+            let meta = metadata.get(path);
+            TagNode {
+                name: path.rsplit('/').next().unwrap_or_default().to_string(),
+                path: path.clone(),
+                count: *count,
+                own_count: *own_count,
+                color: meta.and_then(|m| m.color.clone()),
+                description: meta.and_then(|m| m.description.clone()),
+                pinned: meta.is_some_and(|m| m.pinned),
+                created_at: meta.map(|m| m.created_at),
+                children: tag_tree(counts, metadata, Some(path)),
+            }
         })
         .collect();
 
-    Ok(Json(tags))
+    nodes.sort_by(|a, b| {
+        b.pinned.cmp(&a.pinned)
+            .then_with(|| b.count.cmp(&a.count))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    nodes
 }
 
 
-// Get bookmarks by tag
+// Get bookmarks by tags, see TagQuery
 pub async fn bookmarks_by_tag(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
@@ -74,10 +209,12 @@ pub async fn bookmarks_by_tag(
     let user_id = ObjectId::parse_str(&claims.sub)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
 
+    let (filter, _) = tag_query_filter(&state, user_id, &query).await?;
+
     let collection = state.db.collection::<Bookmark>("bookmarks");
 
     let cursor = collection
-        .find(doc! { "user_id": user_id, "tags": &query.tag }, None)
+        .find(filter, None)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
 
@@ -92,4 +229,668 @@ pub async fn bookmarks_by_tag(
         .collect();
 
     Ok(Json(response))
-}
\ No newline at end of file
+}
+
+
+// Other tags on the bookmarks a tag query matches, with how many of them
+// each is on, to refine the query with
+pub async fn tag_facets(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<TagQuery>,
+) -> Result<Json<Vec<TagSuggestion>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let (filter, included) = tag_query_filter(&state, user_id, &query).await?;
+
+    let facets = tag_usage(&state, filter, Some(doc! { "$nin": included }), MAX_FACETS).await?;
+
+    Ok(Json(facets))
+}
+
+
+// Filter of a tag query and its included tags
+async fn tag_query_filter(
+    state: &AppState,
+    user_id: ObjectId,
+    query: &TagQuery,
+) -> Result<(Document, Vec<String>), (StatusCode, String)> {
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+
+    let (mut included, excluded) = parse_tag_terms(query.tags.as_deref().unwrap_or_default(), &settings);
+
+    if let Some(tag) = &query.tag {
+        included.extend(normalize_tag(tag, &settings));
+    }
+
+    if included.is_empty() && excluded.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Provide tag or tags".to_string()));
+    }
+
+    let mut filter = tags_filter(&included, &excluded, query.match_mode);
+    filter.insert("user_id", user_id);
+
+    Ok((filter, included))
+}
+
+
+// Rename a tag on all the user's bookmarks, merging into the new name if it exists
+pub async fn rename_tag(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(name): Path<String>,
+    Json(body): Json<RenameTagRequest>,
+) -> Result<Json<TagChangeResponse>, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    // Stored tags are normalized, renaming "#Rust" renames "rust"
+    let mut names = normalized_names(&state, user_id, &[body.name.clone(), name]).await?;
+    let target = names.remove(0);
+
+    let bookmarks_updated = replace_tags(&state, user_id, &names, &target).await?;
+
+    Ok(Json(TagChangeResponse { bookmarks_updated }))
+}
+
+
+// Merge several tags into one
+pub async fn merge_tags(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(body): Json<MergeTagsRequest>,
+) -> Result<Json<TagChangeResponse>, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let mut names = normalized_names(&state, user_id, &[std::slice::from_ref(&body.target), &body.sources].concat()).await?;
+    let target = names.remove(0);
+
+    let bookmarks_updated = replace_tags(&state, user_id, &names, &target).await?;
+
+    Ok(Json(TagChangeResponse { bookmarks_updated }))
+}
+
+
+// Remove a tag from all the user's bookmarks
+pub async fn delete_tag(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(name): Path<String>,
+) -> Result<Json<TagChangeResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    // Stored tags are normalized, "#Rust " deletes "rust"
+    let name = normalized_target(&state, user_id, &name).await?;
+
+    let result = state.db.collection::<Bookmark>("bookmarks")
+        .update_many(
+            doc! { "user_id": user_id, "tags": &name },
+            doc! {
+                "$pull": { "tags": &name },
+                "$set": { "updated_at": Utc::now().to_rfc3339() }
+            },
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete tag".to_string()))?;
+
+    state.db.collection::<Tag>("tags")
+        .delete_one(doc! { "user_id": user_id, "name": &name }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete tag".to_string()))?;
+
+    Ok(Json(TagChangeResponse { bookmarks_updated: result.modified_count }))
+}
+
+
+// Tag name from a request, normalized like any other tag
+async fn normalized_target(state: &AppState, user_id: ObjectId, name: &str) -> Result<String, (StatusCode, String)> {
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+
+    normalize_tag(name, &settings).ok_or((StatusCode::BAD_REQUEST, "Tag name is empty".to_string()))
+}
+
+
+// Several tag names from a request, in order, see normalized_target
+async fn normalized_names(state: &AppState, user_id: ObjectId, names: &[String]) -> Result<Vec<String>, (StatusCode, String)> {
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+
+    names
+        .iter()
+        .map(|name| normalize_tag(name, &settings).ok_or((StatusCode::BAD_REQUEST, "Tag name is empty".to_string())))
+        .collect()
+}
+
+
+// Replace the source tags with the target on every bookmark having one of them,
+// in one update_many. Tags below a source move below the target, "lang/rs/async"
+// becomes "lang/rust/async" when "lang/rs" is renamed to "lang/rust".
+// Tags keep their order and a bookmark never ends up with a tag twice.
+// Tag feeds follow the rename
+async fn replace_tags(
+    state: &AppState,
+    user_id: ObjectId,
+    sources: &[String],
+    target: &str,
+) -> Result<u64, (StatusCode, String)> {
+
+    let sources: Vec<&String> = sources.iter().filter(|s| s.as_str() != target).collect();
+
+    if sources.is_empty() {
+        return Ok(0);
+    }
+
+    if sources.iter().any(|s| target.starts_with(&format!("{}/", s))) {
+        return Err((StatusCode::BAD_REQUEST, "A tag cannot be moved below itself".to_string()));
+    }
+
+    let matching: Vec<Document> = sources.iter().map(|s| doc! { "tags": tag_match(s) }).collect();
+
+    let pipeline = vec![doc! { "$set": {
+        "tags": { "$reduce": {
+            "input": "$tags",
+            "initialValue": [],
+            "in": { "$let": {
+                "vars": { "tag": renamed_tag("$$this", &sources, target) },
+                "in": { "$cond": [
+                    { "$in": ["$$tag", "$$value"] },
+                    "$$value",
+                    { "$concatArrays": ["$$value", ["$$tag"]] }
+                ] }
+            } }
+        } },
+        "updated_at": Utc::now().to_rfc3339()
+    } }];
+
+    let result = state.db.collection::<Bookmark>("bookmarks")
+        .update_many(doc! { "user_id": user_id, "$or": matching }, pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tags".to_string()))?;
+
+    let tags_col = state.db.collection::<Tag>("tags");
+
+    let name_matching: Vec<Document> = sources.iter().map(|s| doc! { "name": tag_match(s) }).collect();
+
+    let renames: Vec<(String, String)> = tags_col
+        .find(doc! { "user_id": user_id, "$or": name_matching }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .try_collect::<Vec<Tag>>()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tags".to_string()))?
+        .into_iter()
+        .filter_map(|t| renamed_name(&t.name, &sources, target).map(|to| (t.name, to)))
+        .collect();
+
+    rename_tag_metadata(&state.db, user_id, &renames)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tags".to_string()))?;
+
+    let feed_matching: Vec<Document> = sources.iter().map(|s| doc! { "tag": tag_match(s) }).collect();
+
+    state.db.collection::<Feed>("feeds")
+        .update_many(
+            doc! { "user_id": user_id, "kind": "tag", "$or": feed_matching },
+            vec![doc! { "$set": { "tag": renamed_tag("$tag", &sources, target) } }],
+            None,
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update feeds".to_string()))?;
+
+    Ok(result.modified_count)
+}
+
+
+// Same as renamed_tag for one tag name, None when no source covers it
+fn renamed_name(tag: &str, sources: &[&String], target: &str) -> Option<String> {
+
+    sources.iter().find_map(|source| {
+        if tag == source.as_str() {
+            return Some(target.to_string());
+        }
+        tag.strip_prefix(source.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+            .map(|rest| format!("{}/{}", target, rest))
+    })
+}
+
+
+// Expression giving the new name of the tag in value: the target for a source,
+// the target plus the rest of the path for a tag below a source, otherwise unchanged.
+// $literal keeps tags starting with $ from being read as field paths
+fn renamed_tag(value: &str, sources: &[&String], target: &str) -> Document {
+
+    let mut branches = Vec::new();
+
+    for source in sources {
+        let prefix = format!("{}/", source);
+        let prefix_len = prefix.chars().count() as i64;
+
+        branches.push(doc! {
+            "case": { "$eq": [value, { "$literal": source.as_str() }] },
+            "then": { "$literal": target }
+        });
+        branches.push(doc! {
+            "case": { "$eq": [{ "$substrCP": [value, 0, prefix_len] }, { "$literal": &prefix }] },
+            "then": { "$concat": [
+                { "$literal": format!("{}/", target) },
+                { "$substrCP": [value, prefix_len, { "$strLenCP": value }] }
+            ] }
+        });
+    }
+
+    doc! { "$switch": { "branches": branches, "default": value } }
+}
+
+
+// Get the user's tag normalization settings
+pub async fn get_tag_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<TagSettingsResponse>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+
+    Ok(Json(TagSettingsResponse::from(settings)))
+}
+
+
+// Update the tag normalization settings, then normalize the existing tags with them
+pub async fn update_tag_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(body): Json<UpdateTagSettingsRequest>,
+) -> Result<Json<TagSettingsResponse>, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let mut settings = load_tag_settings(&state.db, user_id).await?;
+
+    if let Some(lowercase) = body.lowercase {
+        settings.lowercase = lowercase;
+    }
+    if let Some(max_length) = body.max_length {
+        settings.max_length = max_length;
+    }
+
+    // Stored aliases are cleaned with the new rules, so they match cleaned tags
+    let aliases = body.aliases.unwrap_or_else(|| std::mem::take(&mut settings.aliases));
+    settings.aliases = clean_aliases(&aliases, &settings)?;
+    settings.updated_at = Utc::now();
+
+    let aliases_doc = mongodb::bson::to_document(&settings.aliases)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid aliases".to_string()))?;
+
+    state.db.collection::<TagSettings>("tag_settings")
+        .update_one(
+            doc! { "user_id": user_id },
+            doc! { "$set": {
+                "lowercase": settings.lowercase,
+                "max_length": settings.max_length,
+                "aliases": aliases_doc,
+                "updated_at": settings.updated_at.to_rfc3339()
+            } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tag settings".to_string()))?;
+
+    let bookmarks_updated = normalize_stored_tags(&state.db, doc! { "user_id": user_id })
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to normalize tags".to_string()))?;
+
+    // Metadata follows the new names of the tags
+    let renames: Vec<(String, String)> = state.db.collection::<Tag>("tags")
+        .find(doc! { "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .try_collect::<Vec<Tag>>()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tags".to_string()))?
+        .into_iter()
+        .filter_map(|t| normalize_tag(&t.name, &settings).map(|to| (t.name, to)))
+        .collect();
+
+    rename_tag_metadata(&state.db, user_id, &renames)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to normalize tags".to_string()))?;
+
+    let mut response = TagSettingsResponse::from(settings);
+    response.bookmarks_updated = Some(bookmarks_updated);
+
+    Ok(Json(response))
+}
+
+
+// Cleans both sides of each alias. An alias pointing to another alias is
+// rejected, so one lookup always gives the final tag
+fn clean_aliases(
+    aliases: &HashMap<String, String>,
+    settings: &TagSettings,
+) -> Result<HashMap<String, String>, (StatusCode, String)> {
+
+    let mut cleaned = HashMap::new();
+
+    for (alias, tag) in aliases {
+        let (Some(alias), Some(tag)) = (clean_tag(alias, settings), clean_tag(tag, settings)) else {
+            return Err((StatusCode::BAD_REQUEST, "Aliases cannot be empty".to_string()));
+        };
+        if alias != tag {
+            cleaned.insert(alias, tag);
+        }
+    }
+
+    if let Some(tag) = cleaned.values().find(|tag| cleaned.contains_key(*tag)) {
+        return Err((StatusCode::BAD_REQUEST, format!("\"{}\" is itself an alias", tag)));
+    }
+
+    Ok(cleaned)
+}
+
+
+// Tags starting with a prefix, most used first
+pub async fn suggest_tags(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<SuggestQuery>,
+) -> Result<Json<Vec<TagSuggestion>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+
+    // Cleaned like the tags it is compared to, "lang/ru" matches "lang/rust" and "ru" matches it too
+    let tag_filter = clean_tag(&query.prefix, &settings).map(|prefix| doc! {
+        "$regex": format!("(^|/){}", escape_regex(&prefix))
+    });
+
+    let mut filter = doc! { "user_id": user_id };
+    if let Some(tag_filter) = &tag_filter {
+        filter.insert("tags", tag_filter);
+    }
+
+    let suggestions = tag_usage(&state, filter, tag_filter, suggestion_limit(query.limit)).await?;
+
+    Ok(Json(suggestions))
+}
+
+
+// Tags most often found on the same bookmarks as a tag
+pub async fn related_tags(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RelatedQuery>,
+) -> Result<Json<Vec<TagSuggestion>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let settings = load_tag_settings(&state.db, user_id).await?;
+    let tag = normalize_tag(&query.tag, &settings)
+        .ok_or((StatusCode::BAD_REQUEST, "Tag required".to_string()))?;
+
+    // The tag and the ones below it are what is being related to
+    let suggestions = tag_usage(
+        &state,
+        doc! { "user_id": user_id, "tags": tag_match(&tag) },
+        Some(doc! { "$not": tag_match(&tag) }),
+        suggestion_limit(query.limit),
+    )
+    .await?;
+
+    Ok(Json(suggestions))
+}
+
+
+// Tags for a bookmark, from the other bookmarks of its domain.
+// A tag named after the domain, like "github" for github.com, comes first
+pub async fn bookmark_tag_suggestions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Query(query): Query<SuggestionsQuery>,
+) -> Result<Json<Vec<TagSuggestion>>, (StatusCode, String)> {
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let bookmark_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid bookmark id".to_string()))?;
+
+    // Only editors can tag, suggestions come from the owner's tags
+    let bookmark = bookmark_access(&state, user_id, bookmark_id, MemberRole::Editor).await?;
+
+    let Some(host) = url_host(&bookmark.url) else {
+        return Ok(Json(Vec::new()));
+    };
+
+    let limit = suggestion_limit(query.limit);
+
+    let mut suggestions = tag_usage(
+        &state,
+        doc! { "user_id": bookmark.user_id, "url": host_match(&host), "_id": { "$ne": bookmark_id } },
+        Some(doc! { "$nin": &bookmark.tags }),
+        limit,
+    )
+    .await?;
+
+    // Site name, "rust-lang" for blog.rust-lang.org and "bbc" for bbc.co.uk
+    let label = domain_label(&host).filter(|label| {
+        !bookmark.tags.contains(label) && !suggestions.iter().any(|s| &s.name == label)
+    });
+
+    if let Some(label) = label {
+        let count = state.db.collection::<Bookmark>("bookmarks")
+            .count_documents(doc! { "user_id": bookmark.user_id, "tags": &label }, None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+        if count > 0 {
+            suggestions.insert(0, TagSuggestion { name: label, count });
+            suggestions.truncate(limit as usize);
+        }
+    }
+
+    Ok(Json(suggestions))
+}
+
+
+// Tags of the bookmarks matching filter, by the number of those bookmarks
+// they are on. tag_filter restricts which tags are counted
+async fn tag_usage(
+    state: &AppState,
+    filter: Document,
+    tag_filter: Option<Document>,
+    limit: i64,
+) -> Result<Vec<TagSuggestion>, (StatusCode, String)> {
+
+    let mut pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$unwind": "$tags" },
+    ];
+    if let Some(tag_filter) = tag_filter {
+        pipeline.push(doc! { "$match": { "tags": tag_filter } });
+    }
+    pipeline.extend([
+        doc! { "$group": { "_id": "$tags", "count": { "$sum": 1 } } },
+        doc! { "$sort": { "count": -1, "_id": 1 } },
+        doc! { "$limit": limit },
+    ]);
+
+    let cursor = state.db.collection::<Bookmark>("bookmarks")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    let results: Vec<Document> = cursor
+        .try_collect()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch tags".to_string()))?;
+
+    results
+        .iter()
+        .map(|r| Ok(TagSuggestion {
+            name: r.get_str("_id").unwrap_or_default().to_string(),
+            count: count_field(r)?,
+        }))
+        .collect()
+}
+
+
+// Create a tag without bookmarks, with its metadata
+pub async fn create_tag(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(body): Json<CreateTagRequest>,
+) -> Result<(StatusCode, Json<TagResponse>), (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    let name = normalized_target(&state, user_id, &body.name).await?;
+
+    let tags_col = state.db.collection::<Tag>("tags");
+
+    let existing = tags_col
+        .find_one(doc! { "user_id": user_id, "name": &name }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    if existing.is_some() {
+        return Err((StatusCode::CONFLICT, "Tag already exists".to_string()));
+    }
+
+    let now = Utc::now();
+
+    let tag = Tag {
+        id: None,
+        user_id,
+        name,
+        color: body.color.filter(|c| !c.is_empty()).map(|c| c.to_lowercase()),
+        description: body.description.filter(|d| !d.is_empty()),
+        pinned: body.pinned,
+        created_at: now,
+        updated_at: now,
+    };
+
+    tags_col
+        .insert_one(&tag, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create tag".to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(TagResponse::from(tag))))
+}
+
+
+// Update the metadata of a tag
+pub async fn update_tag(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(name): Path<String>,
+    Json(body): Json<UpdateTagRequest>,
+) -> Result<Json<TagResponse>, (StatusCode, String)> {
+
+    if let Err(errors) = body.validate() {
+        return Err((StatusCode::BAD_REQUEST, errors.to_string()));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user id".to_string()))?;
+
+    // Metadata is kept under the normalized name, like the tag on bookmarks
+    let name = normalized_target(&state, user_id, &name).await?;
+
+    let tags_col = state.db.collection::<Tag>("tags");
+
+    let existing = tags_col
+        .find_one(doc! { "user_id": user_id, "name": &name }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    // Tags saved before metadata existed get it now
+    if existing.is_none() {
+        let used = state.db.collection::<Bookmark>("bookmarks")
+            .count_documents(doc! { "user_id": user_id, "tags": &name }, None)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+        if used == 0 {
+            return Err((StatusCode::NOT_FOUND, "Tag not found".to_string()));
+        }
+
+        register_tags(&state.db, user_id, std::slice::from_ref(&name))
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tag".to_string()))?;
+    }
+
+    let mut update_doc = doc! {
+        "updated_at": Utc::now().to_rfc3339()
+    };
+
+    if let Some(pinned) = body.pinned {
+        update_doc.insert("pinned", pinned);
+    }
+
+    // Empty strings remove the field
+    let mut unset_doc = Document::new();
+
+    for (field, value) in [
+        ("color", body.color.as_ref().map(|c| c.to_lowercase())),
+        ("description", body.description.clone()),
+    ] {
+        match value {
+            Some(value) if value.is_empty() => { unset_doc.insert(field, ""); }
+            Some(value) => { update_doc.insert(field, value); }
+            None => {}
+        }
+    }
+
+    let mut update = doc! { "$set": update_doc };
+    if !unset_doc.is_empty() {
+        update.insert("$unset", unset_doc);
+    }
+
+    tags_col
+        .update_one(doc! { "user_id": user_id, "name": &name }, update, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update tag".to_string()))?;
+
+    let tag = tags_col
+        .find_one(doc! { "user_id": user_id, "name": &name }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Tag not found".to_string()))?;
+
+    Ok(Json(TagResponse::from(tag)))
+}
@@ -18,6 +18,11 @@ mod models {
     pub mod user;
     pub mod bookmark;
     pub mod collection;
+    pub mod import;
+    pub mod member;
+    pub mod feed;
+    pub mod tag;
+    pub mod search;
 }
 
 mod handlers {
@@ -27,6 +32,10 @@ mod handlers {
     pub mod tag;
     pub mod search;
     pub mod import;
+    pub mod export;
+    pub mod member;
+    pub mod public;
+    pub mod feed;
 }
 
 mod routes {
@@ -36,10 +45,21 @@ mod routes {
     pub mod tag;
     pub mod search;
     pub mod import;
+    pub mod export;
+    pub mod member;
+    pub mod public;
+    pub mod feed;
 }
 
 mod utils {
     pub mod jwt;
+    pub mod firefox;
+    pub mod upload;
+    pub mod filter;
+    pub mod escape;
+    pub mod ordering;
+    pub mod tags;
+    pub mod highlight;
 }
 
 mod middleware {
@@ -52,7 +72,7 @@ use axum::http::{HeaderValue, Method, header};
 use tower_http::cors::CorsLayer;
 
 use config::env::EnvConfig;
-use db::mongo::connect;
+use db::mongo::{connect, supports_transactions};
 use state::app_state::AppState;
 use routes::auth::auth_routes;
 use routes::bookmark::bookmark_routes;
@@ -60,8 +80,13 @@ use routes::collection::collection_routes;
 use routes::tag::tag_routes;
 use routes::search::search_routes;
 use routes::import::import_routes;
+use routes::export::export_routes;
+use routes::member::invitation_routes;
+use routes::public::public_routes;
+use routes::feed::{feed_routes, public_feed_routes};
 use middleware::auth::auth_middleware;
 use handlers::auth::me;
+use handlers::import::{expire_previews, fail_interrupted_jobs, STALE_JOB_CHECK};
 
 
 #[tokio::main]
@@ -71,11 +96,36 @@ async fn main() {
 
     let database = connect(&config).await;
 
+    fail_interrupted_jobs(&database).await;
+    expire_previews(&database).await;
+
+    let transactions = supports_transactions(&database).await;
+
+    if !transactions {
+        println!("MongoDB transactions unavailable, failed imports are undone write by write");
+        println!("MongoDB transactions unavailable, collection delete, merge and duplicate are not atomic");
+    }
+
     let state = AppState {
         db: database,
         jwt_secret: config.jwt_secret.clone(),
+        supports_transactions: transactions,
+        max_upload_bytes: config.max_upload_bytes,
+        public_url: config.public_url.clone(),
+        instance_id: uuid::Uuid::new_v4().simple().to_string(),
     };
 
+    // Jobs of a process that stopped are failed once their heartbeat is stale,
+    // previews nobody committed are removed once they expire
+    let jobs_db = state.db.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(STALE_JOB_CHECK).await;
+            fail_interrupted_jobs(&jobs_db).await;
+            expire_previews(&jobs_db).await;
+        }
+    });
+
     let origins: Vec<HeaderValue> = config.allowed_origins
         .iter()
         .filter_map(|o| o.parse::<HeaderValue>().ok())
@@ -87,6 +137,7 @@ async fn main() {
             Method::GET,
             Method::POST,
             Method::PUT,
+            Method::PATCH,
             Method::DELETE,
         ])
         .allow_headers([
@@ -102,6 +153,9 @@ async fn main() {
         .nest("/tags", tag_routes())
         .nest("/search", search_routes())
         .nest("/import", import_routes())
+        .nest("/export", export_routes())
+        .nest("/invitations", invitation_routes())
+        .nest("/feeds", feed_routes())
         .layer(axum_middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -110,6 +164,8 @@ async fn main() {
     let app = Router::new()
         .route("/health", get(|| async { "OK" }))  // Health endpoint
         .nest("/api/auth", auth_routes())
+        .nest("/p", public_routes())  // Public collection links
+        .nest("/feeds", public_feed_routes())  // Token authenticated feeds
         .nest("/api", protected)
         .layer(cors)
         .with_state(state);
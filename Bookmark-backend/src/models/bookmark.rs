@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
@@ -20,8 +22,17 @@ pub struct Bookmark {
     #[serde(default)]
     pub tags: Vec<String>,
 
+    // Empty for unfiled bookmarks
+    #[serde(default)]
+    pub collection_ids: Vec<ObjectId>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub collection_id: Option<ObjectId>,
+    pub keyword: Option<String>,
+
+    // Manual sort key in each collection by its hex id, and "unfiled"
+    // while in none, see utils::ordering
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub positions: HashMap<String, String>,
 
     pub created_at: DateTime<Utc>,
 
@@ -41,7 +52,23 @@ pub struct CreateBookmarkRequest {
     #[serde(default)]
     pub tags: Vec<String>,
 
+    #[serde(default)]
+    pub collection_ids: Vec<String>,
+
+    // Deprecated, the single collection older clients send
     pub collection_id: Option<String>,
+
+    pub keyword: Option<String>,
+}
+
+impl CreateBookmarkRequest {
+    // collection_ids, or the deprecated collection_id when only that is sent
+    pub fn collections(&self) -> Vec<String> {
+        match &self.collection_id {
+            Some(id) if self.collection_ids.is_empty() && !id.is_empty() => vec![id.clone()],
+            _ => self.collection_ids.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -56,7 +83,54 @@ pub struct UpdateBookmarkRequest {
 
     pub tags: Option<Vec<String>>,
 
+    // Replaces all the collections of the bookmark
+    pub collection_ids: Option<Vec<String>>,
+
+    // Deprecated, replaces the collections with this one, empty unfiles it
+    pub collection_id: Option<String>,
+
+    pub keyword: Option<String>,
+}
+
+impl UpdateBookmarkRequest {
+    // collection_ids, or the deprecated collection_id when only that is sent
+    pub fn collections(&self) -> Option<Vec<String>> {
+        match (&self.collection_ids, &self.collection_id) {
+            (Some(ids), _) => Some(ids.clone()),
+            (None, Some(id)) if id.is_empty() => Some(vec![]),
+            (None, Some(id)) => Some(vec![id.clone()]),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderBookmarkRequest {
+    // List to reorder in, may be left out when the bookmark is in at most one
     pub collection_id: Option<String>,
+
+    // Bookmark to place it after, None puts it first
+    pub after_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveBookmarkRequest {
+    // Collection to take it out of, may be left out when it is in at most one
+    pub from_collection_id: Option<String>,
+
+    // None leaves it unfiled when it was only in from_collection_id
+    pub collection_id: Option<String>,
+
+    // Bookmark to place it after, None puts it last
+    pub after_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddToCollectionRequest {
+    pub collection_id: String,
+
+    // Bookmark to place it after, None puts it last
+    pub after_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -66,7 +140,9 @@ pub struct BookmarkResponse {
     pub url: String,
     pub description: Option<String>,
     pub tags: Vec<String>,
-    pub collection_id: Option<String>,
+    pub collection_ids: Vec<String>,
+    pub keyword: Option<String>,
+    pub positions: HashMap<String, String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -79,9 +155,20 @@ impl From<Bookmark> for BookmarkResponse {
             url: b.url,
             description: b.description,
             tags: b.tags,
-            collection_id: b.collection_id.map(|id| id.to_hex()),
+            collection_ids: b.collection_ids.iter().map(|id| id.to_hex()).collect(),
+            keyword: b.keyword,
+            positions: b.positions,
             created_at: b.created_at,
             updated_at: b.updated_at,
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairResponse {
+    // Bookmarks in a collection that is gone, smart, or not reachable
+    pub dangling: u64,
+    // 0 on a dry run
+    pub repaired: u64,
+    pub bookmark_ids: Vec<String>,
+}
@@ -1,7 +1,9 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
-use validator::Validate;
+use validator::{Validate, ValidationError};
+
+use crate::models::member::MemberRole;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Collection {
@@ -15,24 +17,169 @@ pub struct Collection {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
 
+    // Emoji or icon name, e.g. "📚" or "book-open"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    // Hex color, e.g. "#3b82f6"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_url: Option<String>,
+
+    #[serde(default)]
+    pub pinned: bool,
+
+    #[serde(default)]
+    pub archived: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<ObjectId>,
+
+    #[serde(default)]
+    pub kind: CollectionKind,
+
+    // Manual sort key among its siblings, see utils::ordering
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+
+    // Saved query of a smart collection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<SmartRule>,
+
+    // Public read-only link, None when the collection is private
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<CollectionShare>,
+
     pub created_at: DateTime<Utc>,
 
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionKind {
+    // Holds the bookmarks assigned to it
+    #[default]
+    Manual,
+    // Shows the bookmarks matching its rule
+    Smart,
+}
+
+// Rule of a smart collection, e.g.
+// { "type": "all", "rules": [{ "type": "tag", "tag": "rust" }, { "type": "not", "rule": { "type": "tag", "tag": "archived" } }] }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmartRule {
+    All { rules: Vec<SmartRule> },
+    Any { rules: Vec<SmartRule> },
+    Not { rule: Box<SmartRule> },
+    Tag { tag: String },
+    UrlContains { text: String },
+    TitleContains { text: String },
+    AddedWithinDays { days: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollectionShare {
+    pub slug: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreateCollectionRequest {
     #[validate(length(min = 1, message = "Name required"))]
     pub name: String,
 
     pub description: Option<String>,
+
+    #[validate(length(max = 32, message = "Icon must be at most 32 characters"))]
+    pub icon: Option<String>,
+
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+
+    #[validate(custom = "validate_cover_url")]
+    pub cover_url: Option<String>,
+
+    #[serde(default)]
+    pub pinned: bool,
+
+    pub parent_id: Option<String>,
+
+    // Makes it a smart collection
+    pub rule: Option<SmartRule>,
 }
 
-#[derive(Debug, Deserialize)]
+// An empty icon, color or cover_url removes it
+#[derive(Debug, Deserialize, Validate)]
 pub struct UpdateCollectionRequest {
+    #[validate(length(min = 1, message = "Name required"))]
     pub name: Option<String>,
 
     pub description: Option<String>,
+
+    #[validate(length(max = 32, message = "Icon must be at most 32 characters"))]
+    pub icon: Option<String>,
+
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+
+    #[validate(custom = "validate_cover_url")]
+    pub cover_url: Option<String>,
+
+    pub pinned: Option<bool>,
+
+    pub archived: Option<bool>,
+
+    // Only for smart collections
+    pub rule: Option<SmartRule>,
+}
+
+// "#rgb" or "#rrggbb", empty is allowed so updates can clear it
+pub fn validate_color(color: &str) -> Result<(), ValidationError> {
+
+    let hex = color.strip_prefix('#').unwrap_or_default();
+
+    if color.is_empty() || ((hex.len() == 3 || hex.len() == 6) && hex.bytes().all(|c| c.is_ascii_hexdigit())) {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("color");
+    error.message = Some("Color must be a hex color like #3b82f6".into());
+    Err(error)
+}
+
+// At least 4 characters, empty is allowed so updates can clear it
+fn validate_share_password(password: &str) -> Result<(), ValidationError> {
+
+    if password.is_empty() || password.chars().count() >= 4 {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("password");
+    error.message = Some("Password must be at least 4 characters".into());
+    Err(error)
+}
+
+// http(s) image URL, empty is allowed so updates can clear it
+fn validate_cover_url(url: &str) -> Result<(), ValidationError> {
+
+    if url.is_empty() || ((url.starts_with("http://") || url.starts_with("https://")) && validator::validate_url(url)) {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("cover_url");
+    error.message = Some("Invalid cover URL".into());
+    Err(error)
 }
 
 #[derive(Debug, Serialize)]
@@ -40,7 +187,136 @@ pub struct CollectionResponse {
     pub id: String,
     pub name: String,
     pub description: Option<String>,
+    pub icon: Option<String>,
+    pub color: Option<String>,
+    pub cover_url: Option<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub parent_id: Option<String>,
+    pub kind: CollectionKind,
+    pub rule: Option<SmartRule>,
+    pub position: Option<String>,
+    pub bookmark_count: u64,
+    // Access the caller has, owner for their own collections
+    pub role: MemberRole,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl CollectionResponse {
+    pub fn new(col: Collection, bookmark_count: u64, role: MemberRole) -> Self {
+        Self {
+            id: col.id.unwrap().to_hex(),
+            name: col.name,
+            description: col.description,
+            icon: col.icon,
+            color: col.color,
+            cover_url: col.cover_url,
+            pinned: col.pinned,
+            archived: col.archived,
+            parent_id: col.parent_id.map(|id| id.to_hex()),
+            kind: col.kind,
+            rule: col.rule,
+            position: col.position,
+            bookmark_count,
+            role,
+            created_at: col.created_at,
+            updated_at: col.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveCollectionRequest {
+    // None moves the collection to the top level
+    pub parent_id: Option<String>,
+
+    // Sibling to place it after, None puts it last
+    pub after_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeCollectionRequest {
+    // Collection receiving the bookmarks and sub collections
+    pub target_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderCollectionRequest {
+    // Sibling to place it after, None puts it first
+    pub after_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionTreeNode {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    pub color: Option<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub parent_id: Option<String>,
+    pub kind: CollectionKind,
     pub bookmark_count: u64,
+    // Bookmarks in this collection and all its descendants
+    pub total_bookmark_count: u64,
+    pub children: Vec<CollectionTreeNode>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ShareCollectionRequest {
+    // Left out keeps the current password, empty removes it
+    #[validate(custom = "validate_share_password")]
+    pub password: Option<String>,
+
+    // Open the link to anyone, same as an empty password
+    #[serde(default)]
+    pub remove_password: bool,
+
+    pub expires_at: Option<DateTime<Utc>>,
+
+    // Issue a new slug, the old link stops working
+    #[serde(default)]
+    pub regenerate: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    pub slug: String,
+    pub path: String,
+    pub password_protected: bool,
+    pub expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
+}
+
+impl From<CollectionShare> for ShareResponse {
+    fn from(s: CollectionShare) -> Self {
+        Self {
+            path: format!("/p/{}", s.slug),
+            slug: s.slug,
+            password_protected: s.password_hash.is_some(),
+            expires_at: s.expires_at,
+            created_at: s.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicBookmark {
+    pub title: String,
+    pub url: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicCollection {
+    pub name: String,
+    pub description: Option<String>,
+    pub bookmarks: Vec<PublicBookmark>,
     pub updated_at: DateTime<Utc>,
-}
\ No newline at end of file
+}
@@ -0,0 +1,73 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedKind {
+    Collection,
+    Tag,
+}
+
+// A feed reader subscription, authenticated by the token in its URL
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Feed {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub user_id: ObjectId,
+
+    pub kind: FeedKind,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection_id: Option<ObjectId>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    pub token: String,
+
+    // ETag last served, a different one means the feed changed even when
+    // no bookmark shows it, like after a deletion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+
+    // When a changed ETag was first served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_at: Option<DateTime<Utc>>,
+
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFeedRequest {
+    // Exactly one of these
+    pub collection_id: Option<String>,
+
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedResponse {
+    pub id: String,
+    pub kind: FeedKind,
+    pub collection_id: Option<String>,
+    pub tag: Option<String>,
+    pub atom_url: String,
+    pub rss_url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FeedResponse {
+    pub fn new(f: Feed, public_url: &str) -> Self {
+        Self {
+            id: f.id.unwrap().to_hex(),
+            kind: f.kind,
+            collection_id: f.collection_id.map(|id| id.to_hex()),
+            tag: f.tag,
+            atom_url: format!("{}/feeds/{}/atom", public_url, f.token),
+            rss_url: format!("{}/feeds/{}/rss", public_url, f.token),
+            created_at: f.created_at,
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+
+// Bookmark parsed from an uploaded export, before it is saved
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParsedBookmark {
+    pub title: String,
+    pub url: String,
+    // Folder names from the top level down, empty when not in a folder
+    #[serde(default)]
+    pub folder_path: Vec<String>,
+    pub tags: Vec<String>,
+    pub keyword: Option<String>,
+    pub added_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Preview,
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportItemError {
+    pub url: String,
+    pub error: String,
+}
+
+// Parsed entry of a dry run, kept in import_items until the job is committed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportItem {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub job_id: ObjectId,
+
+    // Order of the entry in the uploaded file
+    pub position: i64,
+
+    pub bookmark: ParsedBookmark,
+
+    // Entries of abandoned previews are removed by a TTL index on this field
+    pub created_at: mongodb::bson::DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportJob {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub user_id: ObjectId,
+
+    pub status: ImportStatus,
+
+    pub total: i64,
+
+    pub processed: i64,
+
+    pub imported: i64,
+
+    pub skipped: i64,
+
+    #[serde(default)]
+    pub updated: i64,
+
+    pub collections_created: i64,
+
+    #[serde(default)]
+    pub errors: Vec<ImportItemError>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    // Server process working on a pending or running job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<String>,
+
+    // Refreshed by the worker while the job is pending or running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat_at: Option<mongodb::bson::DateTime>,
+
+    pub created_at: DateTime<Utc>,
+
+    pub updated_at: DateTime<Utc>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportJobResponse {
+    pub id: String,
+    pub status: ImportStatus,
+    pub total: i64,
+    pub processed: i64,
+    pub imported: i64,
+    pub skipped: i64,
+    pub updated: i64,
+    pub collections_created: i64,
+    pub errors: Vec<ImportItemError>,
+    pub message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<ImportJob> for ImportJobResponse {
+    fn from(j: ImportJob) -> Self {
+        Self {
+            id: j.id.unwrap().to_hex(),
+            status: j.status,
+            total: j.total,
+            processed: j.processed,
+            imported: j.imported,
+            skipped: j.skipped,
+            updated: j.updated,
+            collections_created: j.collections_created,
+            errors: j.errors,
+            message: j.message,
+            created_at: j.created_at,
+            updated_at: j.updated_at,
+            finished_at: j.finished_at,
+        }
+    }
+}
+
+// What to do with an imported URL the user already has
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictAction {
+    #[default]
+    Skip,
+    Overwrite,
+    MergeTags,
+    Duplicate,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ImportResolution {
+    #[serde(default)]
+    pub default: ConflictAction,
+
+    // Per-URL choices, overriding the default
+    #[serde(default)]
+    pub choices: HashMap<String, ConflictAction>,
+}
+
+impl ImportResolution {
+    pub fn action_for(&self, url: &str) -> ConflictAction {
+        self.choices.get(url).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewFolder {
+    pub name: String,
+    pub path: Vec<String>,
+    // Bookmarks directly in this folder
+    pub bookmark_count: usize,
+    // "create" or "reuse"
+    pub action: String,
+    pub collection_id: Option<String>,
+    pub children: Vec<PreviewFolder>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewConflict {
+    pub url: String,
+    pub title: String,
+    pub existing_id: String,
+    pub existing_title: String,
+    pub existing_tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportPreview {
+    pub job_id: String,
+    pub total: usize,
+    pub new_bookmarks: usize,
+    pub unfiled: usize,
+    pub folders: Vec<PreviewFolder>,
+    pub conflicts: Vec<PreviewConflict>,
+    pub invalid: Vec<ImportItemError>,
+}
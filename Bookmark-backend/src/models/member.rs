@@ -0,0 +1,91 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+// Ordered from least to most access, so roles can be compared
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberRole {
+    Viewer,
+    Editor,
+    Admin,
+    // Never stored, the owner is the collection's user_id
+    Owner,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberStatus {
+    Pending,
+    Accepted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollectionMember {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub collection_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    pub email: String,
+
+    pub role: MemberRole,
+
+    pub status: MemberStatus,
+
+    pub invited_by: ObjectId,
+
+    pub created_at: DateTime<Utc>,
+
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct InviteMemberRequest {
+    #[validate(email(message = "Invalid email"))]
+    pub email: String,
+
+    pub role: MemberRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: MemberRole,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    // None for the owner, who has no membership entry
+    pub id: Option<String>,
+    pub user_id: String,
+    pub email: String,
+    pub role: MemberRole,
+    pub status: MemberStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<CollectionMember> for MemberResponse {
+    fn from(m: CollectionMember) -> Self {
+        Self {
+            id: Some(m.id.unwrap().to_hex()),
+            user_id: m.user_id.to_hex(),
+            email: m.email,
+            role: m.role,
+            status: m.status,
+            created_at: m.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvitationResponse {
+    pub id: String,
+    pub collection_id: String,
+    pub collection_name: String,
+    pub role: MemberRole,
+    pub invited_by: String,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+use crate::models::bookmark::BookmarkResponse;
+use crate::models::tag::TagSuggestion;
+
+// Matched terms wrapped in <mark>, the rest HTML escaped.
+// Only set for fields with a match
+#[derive(Debug, Serialize, Default)]
+pub struct SearchHighlights {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    // A snippet around the first match of long descriptions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub bookmark: BookmarkResponse,
+
+    // Text relevance, higher is better
+    pub score: f64,
+
+    pub highlights: SearchHighlights,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionFacet {
+    pub id: String,
+    pub name: String,
+    pub count: u64,
+}
+
+// Most frequent tags and collections among all results
+#[derive(Debug, Serialize)]
+pub struct SearchFacets {
+    pub tags: Vec<TagSuggestion>,
+    pub collections: Vec<CollectionFacet>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+    pub facets: SearchFacets,
+}
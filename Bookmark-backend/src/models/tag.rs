@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+use crate::models::collection::validate_color;
+
+// Longest tag kept when no setting says otherwise
+pub const DEFAULT_TAG_MAX_LENGTH: u32 = 50;
+
+fn default_lowercase() -> bool {
+    true
+}
+
+fn default_max_length() -> u32 {
+    DEFAULT_TAG_MAX_LENGTH
+}
+
+// How a user's tags are normalized, one document per user.
+// Users without one get the defaults
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TagSettings {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub user_id: ObjectId,
+
+    #[serde(default = "default_lowercase")]
+    pub lowercase: bool,
+
+    // In characters
+    #[serde(default = "default_max_length")]
+    pub max_length: u32,
+
+    // Normalized alias to the tag it stands for, e.g. "rust-lang" -> "rust"
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TagSettings {
+    pub fn defaults(user_id: ObjectId) -> Self {
+        Self {
+            id: None,
+            user_id,
+            lowercase: default_lowercase(),
+            max_length: default_max_length(),
+            aliases: HashMap::new(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateTagSettingsRequest {
+    pub lowercase: Option<bool>,
+
+    #[validate(range(min = 1, max = 200, message = "max_length must be between 1 and 200"))]
+    pub max_length: Option<u32>,
+
+    // Replaces all aliases
+    pub aliases: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagSettingsResponse {
+    pub lowercase: bool,
+    pub max_length: u32,
+    pub aliases: HashMap<String, String>,
+
+    // Existing bookmarks normalized again after an update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bookmarks_updated: Option<u64>,
+}
+
+impl From<TagSettings> for TagSettingsResponse {
+    fn from(s: TagSettings) -> Self {
+        Self {
+            lowercase: s.lowercase,
+            max_length: s.max_length,
+            aliases: s.aliases,
+            bookmarks_updated: None,
+        }
+    }
+}
+
+// Properties of a tag, one document per user and tag name. Tags are still
+// stored as strings on bookmarks, this keeps tags without bookmarks too
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tag {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub user_id: ObjectId,
+
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub pinned: bool,
+
+    pub created_at: DateTime<Utc>,
+
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateTagRequest {
+    #[validate(length(min = 1, message = "Name required"))]
+    pub name: String,
+
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+
+    #[validate(length(max = 500, message = "Description must be at most 500 characters"))]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+// An empty color or description removes it
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateTagRequest {
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+
+    #[validate(length(max = 500, message = "Description must be at most 500 characters"))]
+    pub description: Option<String>,
+
+    pub pinned: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagResponse {
+    pub name: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub pinned: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<Tag> for TagResponse {
+    fn from(t: Tag) -> Self {
+        Self {
+            name: t.name,
+            color: t.color,
+            description: t.description,
+            pinned: t.pinned,
+            created_at: t.created_at,
+            updated_at: t.updated_at,
+        }
+    }
+}
+
+// A level of the / tag hierarchy, "rust" at path "lang/rust"
+#[derive(Debug, Serialize)]
+pub struct TagNode {
+    pub name: String,
+    pub path: String,
+
+    // Bookmarks with this tag or one below it, each counted once
+    pub count: u64,
+
+    // Bookmarks with exactly this tag
+    pub own_count: u64,
+
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub pinned: bool,
+
+    // None for levels that only exist as part of longer tags
+    pub created_at: Option<DateTime<Utc>>,
+
+    pub children: Vec<TagNode>,
+}
+
+// A suggested tag and how many bookmarks it is on
+#[derive(Debug, Serialize)]
+pub struct TagSuggestion {
+    pub name: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RenameTagRequest {
+    #[validate(length(min = 1, message = "Name required"))]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MergeTagsRequest {
+    #[validate(length(min = 1, message = "At least one source tag required"))]
+    pub sources: Vec<String>,
+
+    #[validate(length(min = 1, message = "Target tag required"))]
+    pub target: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagChangeResponse {
+    // Bookmarks whose tags changed
+    pub bookmarks_updated: u64,
+}
@@ -1,5 +1,5 @@
 use axum::{
-    routing::{get, post, put, delete},
+    routing::{get, post, delete},
     Router,
 };
 
@@ -10,10 +10,22 @@ use crate::handlers::bookmark::{
     get_bookmark,
     update_bookmark,
     delete_bookmark,
+    reorder_bookmark,
+    move_bookmark,
+    repair_bookmarks,
+    add_to_collection,
+    remove_from_collection,
 };
+use crate::handlers::tag::bookmark_tag_suggestions;
 
 pub fn bookmark_routes() -> Router<AppState> {
     Router::new()
         .route("/", post(create_bookmark).get(list_bookmarks))
+        .route("/repair", post(repair_bookmarks))
         .route("/:id", get(get_bookmark).put(update_bookmark).delete(delete_bookmark))
+        .route("/:id/reorder", post(reorder_bookmark))
+        .route("/:id/move", post(move_bookmark))
+        .route("/:id/collections", post(add_to_collection))
+        .route("/:id/collections/:collection_id", delete(remove_from_collection))
+        .route("/:id/tag-suggestions", get(bookmark_tag_suggestions))
 }
\ No newline at end of file
@@ -1,5 +1,5 @@
 use axum::{
-    routing::{get, post, put, delete},
+    routing::{get, post},
     Router,
 };
 
@@ -10,10 +10,26 @@ use crate::handlers::collection::{
     get_collection,
     update_collection,
     delete_collection,
+    move_collection,
+    reorder_collection,
+    merge_collection,
+    duplicate_collection,
+    collection_tree,
+    get_share,
+    share_collection,
+    unshare_collection,
 };
+use crate::routes::member::member_routes;
 
 pub fn collection_routes() -> Router<AppState> {
     Router::new()
         .route("/", post(create_collection).get(list_collections))
+        .route("/tree", get(collection_tree))
         .route("/:id", get(get_collection).put(update_collection).delete(delete_collection))
+        .route("/:id/move", post(move_collection))
+        .route("/:id/reorder", post(reorder_collection))
+        .route("/:id/merge", post(merge_collection))
+        .route("/:id/duplicate", post(duplicate_collection))
+        .route("/:id/share", get(get_share).put(share_collection).delete(unshare_collection))
+        .nest("/:id/members", member_routes())
 }
\ No newline at end of file
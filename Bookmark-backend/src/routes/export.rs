@@ -0,0 +1,12 @@
+use axum::{
+    routing::get,
+    Router,
+};
+
+use crate::state::app_state::AppState;
+use crate::handlers::export::export_bookmarks;
+
+pub fn export_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(export_bookmarks))
+}
@@ -0,0 +1,26 @@
+use axum::{
+    routing::{get, delete},
+    Router,
+};
+
+use crate::state::app_state::AppState;
+use crate::handlers::feed::{
+    create_feed,
+    list_feeds,
+    delete_feed,
+    atom_feed,
+    rss_feed,
+};
+
+pub fn feed_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_feeds).post(create_feed))
+        .route("/:id", delete(delete_feed))
+}
+
+// Feed readers authenticate with the token in the URL, mounted outside the auth middleware
+pub fn public_feed_routes() -> Router<AppState> {
+    Router::new()
+        .route("/:token/atom", get(atom_feed))
+        .route("/:token/rss", get(rss_feed))
+}
@@ -1,12 +1,23 @@
 use axum::{
-    routing::post,
+    extract::DefaultBodyLimit,
+    routing::{get, post},
     Router,
 };
 
 use crate::state::app_state::AppState;
-use crate::handlers::import::import_bookmarks;
+use crate::handlers::import::{
+    import_bookmarks,
+    list_import_jobs,
+    get_import_job,
+    cancel_import_job,
+    commit_import,
+};
 
 pub fn import_routes() -> Router<AppState> {
     Router::new()
-        .route("/", post(import_bookmarks))
-}
\ No newline at end of file
+        // The upload limit is enforced by the handler while streaming
+        .route("/", post(import_bookmarks).layer(DefaultBodyLimit::disable()).get(list_import_jobs))
+        .route("/:id", get(get_import_job))
+        .route("/:id/commit", post(commit_import))
+        .route("/:id/cancel", post(cancel_import_job))
+}
@@ -0,0 +1,29 @@
+use axum::{
+    routing::{get, post, put},
+    Router,
+};
+
+use crate::state::app_state::AppState;
+use crate::handlers::member::{
+    list_members,
+    invite_member,
+    update_member,
+    remove_member,
+    list_invitations,
+    accept_invitation,
+    decline_invitation,
+};
+
+// Nested under /collections/:id/members
+pub fn member_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_members).post(invite_member))
+        .route("/:member_id", put(update_member).delete(remove_member))
+}
+
+pub fn invitation_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_invitations))
+        .route("/:id/accept", post(accept_invitation))
+        .route("/:id/decline", post(decline_invitation))
+}
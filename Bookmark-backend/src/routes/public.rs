@@ -0,0 +1,13 @@
+use axum::{
+    routing::get,
+    Router,
+};
+
+use crate::state::app_state::AppState;
+use crate::handlers::public::{public_collection, unlock_public_collection};
+
+// Mounted outside the auth middleware
+pub fn public_routes() -> Router<AppState> {
+    Router::new()
+        .route("/:slug", get(public_collection).post(unlock_public_collection))
+}
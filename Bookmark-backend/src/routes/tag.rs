@@ -1,13 +1,32 @@
 use axum::{
-    routing::get,
+    routing::{get, post, put},
     Router,
 };
 
 use crate::state::app_state::AppState;
-use crate::handlers::tag::{list_tags, bookmarks_by_tag};
+use crate::handlers::tag::{
+    list_tags,
+    bookmarks_by_tag,
+    rename_tag,
+    merge_tags,
+    delete_tag,
+    get_tag_settings,
+    update_tag_settings,
+    suggest_tags,
+    related_tags,
+    tag_facets,
+    create_tag,
+    update_tag,
+};
 
 pub fn tag_routes() -> Router<AppState> {
     Router::new()
-        .route("/", get(list_tags))
+        .route("/", get(list_tags).post(create_tag))
         .route("/bookmarks", get(bookmarks_by_tag))
+        .route("/facets", get(tag_facets))
+        .route("/suggest", get(suggest_tags))
+        .route("/related", get(related_tags))
+        .route("/merge", post(merge_tags))
+        .route("/settings", get(get_tag_settings).put(update_tag_settings))
+        .route("/:name", put(rename_tag).patch(update_tag).delete(delete_tag))
 }
\ No newline at end of file
@@ -4,4 +4,9 @@ use mongodb::Database;
 pub struct AppState {
     pub db: Database,
     pub jwt_secret: String,
+    pub supports_transactions: bool,
+    pub max_upload_bytes: usize,
+    pub public_url: String,
+    // Tells apart the server processes sharing the database, see import jobs
+    pub instance_id: String,
 }
\ No newline at end of file
@@ -0,0 +1,87 @@
+// Escape text for XML and HTML output
+pub fn xml(text: &str) -> String {
+
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+
+// Quote a CSV field when it contains a separator, quote or newline.
+// Fields a spreadsheet would run as a formula get a leading '
+pub fn csv(text: &str) -> String {
+
+    let text = if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    };
+
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+
+// Escape user text written into Markdown, kept on a single line
+pub fn markdown(text: &str) -> String {
+
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.replace("\r\n", "\n").chars() {
+        match c {
+            '\\' | '[' | ']' | '*' | '_' | '`' | '<' | '#' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' | '\n' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_separators() {
+        assert_eq!(csv("plain"), "plain");
+        assert_eq!(csv("a,b"), "\"a,b\"");
+        assert_eq!(csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_defuses_formulas() {
+        assert_eq!(csv("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv("+1"), "'+1");
+        assert_eq!(csv("-1"), "'-1");
+        assert_eq!(csv("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv("a=b"), "a=b");
+    }
+
+    #[test]
+    fn markdown_escapes_syntax_and_newlines() {
+        assert_eq!(markdown("plain text"), "plain text");
+        assert_eq!(markdown("[link](x)"), "\\[link\\](x)");
+        assert_eq!(markdown("*bold* _em_ `code`"), "\\*bold\\* \\_em\\_ \\`code\\`");
+        assert_eq!(markdown("line one\r\nline two"), "line one line two");
+        assert_eq!(markdown("back\\slash"), "back\\\\slash");
+    }
+}
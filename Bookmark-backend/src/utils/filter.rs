@@ -0,0 +1,313 @@
+use axum::http::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId, to_bson, Document, Regex};
+use serde::Deserialize;
+use chrono::{Duration, Utc};
+
+use crate::models::collection::{Collection, CollectionKind, SmartRule};
+
+
+// Filters shared by bookmark listing and export
+#[derive(Debug, Deserialize, Default)]
+pub struct BookmarkFilter {
+    pub collection_id: Option<String>,
+    pub tag: Option<String>,
+}
+
+
+pub fn bookmark_filter(user_id: ObjectId, filter: &BookmarkFilter) -> Result<Document, (StatusCode, String)> {
+
+    let mut query = doc! { "user_id": user_id };
+
+    if let Some(collection_id) = &filter.collection_id {
+        let col_id = ObjectId::parse_str(collection_id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid collection id".to_string()))?;
+        query.insert("collection_ids", col_id);
+    }
+    if let Some(tag) = &filter.tag {
+        query.insert("tags", tag_match(tag));
+    }
+
+    Ok(query)
+}
+
+
+// Nesting limit for smart collection rules
+const MAX_RULE_DEPTH: usize = 8;
+
+
+// Bookmarks shown by a collection: the assigned ones, or for a smart
+// collection the owner's bookmarks matching its rule
+pub fn collection_bookmark_filter(col: &Collection) -> Result<Document, (StatusCode, String)> {
+
+    match (&col.kind, &col.rule) {
+        (CollectionKind::Smart, Some(rule)) => Ok(doc! {
+            "$and": [
+                { "user_id": col.user_id },
+                rule_filter(rule)?
+            ]
+        }),
+        _ => Ok(doc! { "collection_ids": col.id.unwrap() }),
+    }
+}
+
+
+// Compile a smart collection rule to a Mongo query
+pub fn rule_filter(rule: &SmartRule) -> Result<Document, (StatusCode, String)> {
+    compile_rule(rule, 0)
+}
+
+
+fn compile_rule(rule: &SmartRule, depth: usize) -> Result<Document, (StatusCode, String)> {
+
+    if depth >= MAX_RULE_DEPTH {
+        return Err((StatusCode::BAD_REQUEST, "Rule is nested too deeply".to_string()));
+    }
+
+    let invalid = |message: &str| Err((StatusCode::BAD_REQUEST, message.to_string()));
+
+    match rule {
+        SmartRule::All { rules } | SmartRule::Any { rules } => {
+            if rules.is_empty() {
+                return invalid("Rule groups need at least one rule");
+            }
+
+            let compiled = rules
+                .iter()
+                .map(|r| compile_rule(r, depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let op = if matches!(rule, SmartRule::All { .. }) { "$and" } else { "$or" };
+            Ok(doc! { op: compiled })
+        }
+        SmartRule::Not { rule } => Ok(doc! { "$nor": [compile_rule(rule, depth + 1)?] }),
+        SmartRule::Tag { tag } => {
+            if tag.trim().is_empty() {
+                return invalid("Tag rule needs a tag");
+            }
+            Ok(doc! { "tags": tag_match(tag.trim()) })
+        }
+        SmartRule::UrlContains { text } | SmartRule::TitleContains { text } => {
+            if text.is_empty() {
+                return invalid("Contains rule needs text");
+            }
+            let field = if matches!(rule, SmartRule::UrlContains { .. }) { "url" } else { "title" };
+            Ok(doc! { field: { "$regex": escape_regex(text), "$options": "i" } })
+        }
+        SmartRule::AddedWithinDays { days } => {
+            if *days == 0 {
+                return invalid("Days must be at least 1");
+            }
+            // Serialized the same way as stored created_at values, so the strings compare in time order
+            let since = to_bson(&(Utc::now() - Duration::days(*days as i64)))
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid date".to_string()))?;
+            Ok(doc! { "created_at": { "$gte": since } })
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TagMatch {
+    // Bookmarks with every included tag
+    #[default]
+    All,
+    // Bookmarks with at least one of them
+    Any,
+}
+
+
+// Bookmarks with the included tags, all or any of them, and none of the
+// excluded ones. Every tag also matches the tags below it
+pub fn tags_filter(included: &[String], excluded: &[String], match_mode: TagMatch) -> Document {
+
+    let mut filter = Document::new();
+
+    if !included.is_empty() {
+        let matches: Vec<Document> = included.iter().map(|t| doc! { "tags": tag_match(t) }).collect();
+        let op = if match_mode == TagMatch::All { "$and" } else { "$or" };
+        filter.insert(op, matches);
+    }
+    if !excluded.is_empty() {
+        let matches: Vec<Document> = excluded.iter().map(|t| doc! { "tags": tag_match(t) }).collect();
+        filter.insert("$nor", matches);
+    }
+
+    filter
+}
+
+
+// Matches a tag and the tags below it in the / hierarchy,
+// "lang/rust" also matches "lang/rust/async" but not "lang/rusty"
+pub fn tag_match(tag: &str) -> Document {
+    let descendants = Regex {
+        pattern: format!("^{}/", escape_regex(tag)),
+        options: String::new(),
+    };
+    doc! { "$in": [tag, descendants] }
+}
+
+
+// Host of a URL without a leading "www.", lowercased
+pub fn url_host(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let host = parsed.host_str()?.to_lowercase();
+    Some(host.strip_prefix("www.").map(str::to_string).unwrap_or(host))
+}
+
+
+// Second level labels country domains register names under, as in bbc.co.uk
+const GENERIC_SECOND_LEVEL: &[&str] = &[
+    "ac", "co", "com", "edu", "gen", "go", "gob", "gov", "govt", "gv", "ltd",
+    "me", "mil", "ne", "net", "nhs", "nic", "nom", "or", "org", "plc", "sch",
+];
+
+// Hosting platforms whose users each get a subdomain, as in rust-lang.github.io
+const PLATFORM_SUFFIXES: &[&str] = &[
+    "appspot.com", "blogspot.com", "firebaseapp.com", "github.io", "gitlab.io",
+    "herokuapp.com", "netlify.app", "pages.dev", "vercel.app", "web.app",
+];
+
+
+// Name of the site a host belongs to, the label right before its public suffix:
+// "rust-lang" for blog.rust-lang.org, "bbc" for www.bbc.co.uk and
+// "serde-rs" for serde-rs.github.io. None for IP addresses
+pub fn domain_label(host: &str) -> Option<String> {
+
+    if host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+        return None;
+    }
+
+    let labels: Vec<&str> = host.split('.').filter(|l| !l.is_empty()).collect();
+    let count = labels.len();
+
+    let suffix_len = match PLATFORM_SUFFIXES.iter().find(|s| host.ends_with(&format!(".{}", s))) {
+        Some(suffix) => suffix.split('.').count(),
+        None if count > 2 && labels[count - 1].len() == 2 && GENERIC_SECOND_LEVEL.contains(&labels[count - 2]) => 2,
+        None => 1,
+    };
+
+    // A host that is only a suffix is its own label
+    let label = match count.checked_sub(suffix_len + 1) {
+        Some(index) => labels[index],
+        None => labels.first()?,
+    };
+
+    Some(label.to_string())
+}
+
+
+// Matches the URLs of a host, with or without "www." and a port
+pub fn host_match(host: &str) -> Regex {
+    Regex {
+        pattern: format!("^https?://(www\\.)?{}(:[0-9]+)?([/?#]|$)", escape_regex(host)),
+        options: "i".to_string(),
+    }
+}
+
+
+// Match text literally inside a $regex
+pub fn escape_regex(text: &str) -> String {
+
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#-/".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> SmartRule {
+        SmartRule::Tag { tag: name.to_string() }
+    }
+
+    // A tag rule under depth levels of Not
+    fn nested(depth: usize) -> SmartRule {
+        (0..depth).fold(tag("rust"), |rule, _| SmartRule::Not { rule: Box::new(rule) })
+    }
+
+    #[test]
+    fn rules_up_to_the_depth_limit_compile() {
+        assert!(rule_filter(&nested(MAX_RULE_DEPTH - 1)).is_ok());
+    }
+
+    #[test]
+    fn rules_past_the_depth_limit_are_rejected() {
+        let (status, message) = rule_filter(&nested(MAX_RULE_DEPTH)).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message, "Rule is nested too deeply");
+    }
+
+    #[test]
+    fn groups_count_towards_the_depth() {
+        let rule = (0..MAX_RULE_DEPTH).fold(tag("rust"), |rule, _| SmartRule::Any { rules: vec![tag("go"), rule] });
+        assert!(rule_filter(&rule).is_err());
+
+        let rule = (1..MAX_RULE_DEPTH).fold(tag("rust"), |rule, _| SmartRule::All { rules: vec![rule] });
+        assert!(rule_filter(&rule).is_ok());
+    }
+
+    #[test]
+    fn empty_rules_are_rejected() {
+        assert!(rule_filter(&SmartRule::All { rules: vec![] }).is_err());
+        assert!(rule_filter(&tag("  ")).is_err());
+        assert!(rule_filter(&SmartRule::TitleContains { text: String::new() }).is_err());
+        assert!(rule_filter(&SmartRule::AddedWithinDays { days: 0 }).is_err());
+    }
+
+    #[test]
+    fn rules_compile_to_queries() {
+        let rule = SmartRule::All { rules: vec![
+            tag(" lang/rust "),
+            SmartRule::Not { rule: Box::new(SmartRule::UrlContains { text: "a.b".to_string() }) },
+        ] };
+
+        let descendants = Regex { pattern: "^lang\\/rust/".to_string(), options: String::new() };
+        assert_eq!(rule_filter(&rule).unwrap(), doc! { "$and": [
+            { "tags": { "$in": ["lang/rust", descendants] } },
+            { "$nor": [{ "url": { "$regex": "a\\.b", "$options": "i" } }] }
+        ] });
+    }
+
+    #[test]
+    fn domain_label_skips_the_public_suffix() {
+        assert_eq!(domain_label("blog.rust-lang.org").as_deref(), Some("rust-lang"));
+        assert_eq!(domain_label("github.com").as_deref(), Some("github"));
+        assert_eq!(domain_label("localhost").as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn domain_label_skips_generic_second_levels() {
+        assert_eq!(domain_label("bbc.co.uk").as_deref(), Some("bbc"));
+        assert_eq!(domain_label("news.bbc.co.uk").as_deref(), Some("bbc"));
+        assert_eq!(domain_label("abc.net.au").as_deref(), Some("abc"));
+        assert_eq!(domain_label("www.ox.ac.uk").as_deref(), Some("ox"));
+        assert_eq!(domain_label("folha.com.br").as_deref(), Some("folha"));
+        // Only under country domains
+        assert_eq!(domain_label("docs.net.com").as_deref(), Some("net"));
+        assert_eq!(domain_label("t.co").as_deref(), Some("t"));
+        assert_eq!(domain_label("co.uk").as_deref(), Some("co"));
+    }
+
+    #[test]
+    fn domain_label_uses_the_platform_user() {
+        assert_eq!(domain_label("serde-rs.github.io").as_deref(), Some("serde-rs"));
+        assert_eq!(domain_label("docs.example.pages.dev").as_deref(), Some("example"));
+        assert_eq!(domain_label("github.io").as_deref(), Some("github"));
+    }
+
+    #[test]
+    fn domain_label_ignores_ip_addresses() {
+        assert_eq!(domain_label("127.0.0.1"), None);
+        assert_eq!(domain_label("[::1]"), None);
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::DateTime;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::models::import::ParsedBookmark;
+
+// Every SQLite database starts with this header
+pub const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+// moz_bookmarks.type values
+const TYPE_BOOKMARK: i64 = 1;
+const TYPE_FOLDER: i64 = 2;
+
+// Built-in root folders, these are not turned into collections
+const ROOT_GUIDS: [&str; 6] = [
+    "root________",
+    "menu________",
+    "toolbar_____",
+    "tags________",
+    "unfiled_____",
+    "mobile______",
+];
+
+const TAGS_ROOT_GUID: &str = "tags________";
+
+
+struct Item {
+    kind: i64,
+    place_id: Option<i64>,
+    parent: Option<i64>,
+    title: Option<String>,
+    date_added: Option<i64>,
+}
+
+
+pub fn is_places_sqlite(bytes: &[u8]) -> bool {
+    bytes.starts_with(SQLITE_MAGIC)
+}
+
+
+// Parse a Firefox places.sqlite database
+pub fn parse_places_sqlite(bytes: &[u8]) -> Result<Vec<ParsedBookmark>, String> {
+
+    // SQLite can only open files, so copy the upload to a temp file first
+    let path = std::env::temp_dir().join(format!("places-{}.sqlite", uuid::Uuid::new_v4()));
+
+    std::fs::write(&path, bytes)
+        .map_err(|_| "Failed to store uploaded database".to_string())?;
+
+    let result = read_places(&path);
+
+    let _ = std::fs::remove_file(&path);
+
+    result
+}
+
+
+fn read_places(path: &std::path::Path) -> Result<Vec<ParsedBookmark>, String> {
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|_| "Invalid places.sqlite file".to_string())?;
+
+    // Load the whole bookmark tree
+    let mut items: HashMap<i64, Item> = HashMap::new();
+    let mut roots: HashSet<i64> = HashSet::new();
+    let mut tags_root: Option<i64> = None;
+
+    let mut stmt = conn
+        .prepare("SELECT id, type, fk, parent, title, dateAdded, guid FROM moz_bookmarks")
+        .map_err(|_| "File is not a Firefox places database".to_string())?;
+
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                Item {
+                    kind: row.get(1)?,
+                    place_id: row.get(2)?,
+                    parent: row.get(3)?,
+                    title: row.get(4)?,
+                    date_added: row.get(5)?,
+                },
+                row.get::<_, Option<String>>(6)?,
+            ))
+        })
+        .map_err(|_| "Failed to read bookmarks".to_string())?;
+
+    for row in rows {
+        let (id, item, guid) = row.map_err(|_| "Failed to read bookmarks".to_string())?;
+
+        if let Some(guid) = guid.as_deref() {
+            if ROOT_GUIDS.contains(&guid) {
+                roots.insert(id);
+            }
+            if guid == TAGS_ROOT_GUID {
+                tags_root = Some(id);
+            }
+        }
+
+        items.insert(id, item);
+    }
+
+    // Load URLs and page titles
+    let mut places: HashMap<i64, (String, Option<String>)> = HashMap::new();
+
+    let mut stmt = conn
+        .prepare("SELECT id, url, title FROM moz_places")
+        .map_err(|_| "File is not a Firefox places database".to_string())?;
+
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?)))
+        .map_err(|_| "Failed to read places".to_string())?;
+
+    for row in rows {
+        let (id, url, title) = row.map_err(|_| "Failed to read places".to_string())?;
+        places.insert(id, (url, title));
+    }
+
+    // Keywords are optional, older profiles store them differently
+    let keywords = read_keywords(&conn);
+
+    // Tags are folders under the tags root, holding one entry per tagged place
+    let mut place_tags: HashMap<i64, Vec<String>> = HashMap::new();
+
+    // Walk in id order so tags keep the order they were created in
+    let mut ids: Vec<i64> = items.keys().copied().collect();
+    ids.sort();
+
+    if let Some(tags_root) = tags_root {
+        for item in ids.iter().map(|id| &items[id]) {
+            if item.kind != TYPE_BOOKMARK {
+                continue;
+            }
+
+            let Some(tag_folder) = item.parent.and_then(|p| items.get(&p)) else {
+                continue;
+            };
+
+            if tag_folder.kind != TYPE_FOLDER || tag_folder.parent != Some(tags_root) {
+                continue;
+            }
+
+            if let (Some(place_id), Some(tag)) = (item.place_id, &tag_folder.title) {
+                let tags = place_tags.entry(place_id).or_default();
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+    }
+
+    let mut bookmarks = Vec::new();
+
+    for id in ids {
+        let item = &items[&id];
+
+        if item.kind != TYPE_BOOKMARK {
+            continue;
+        }
+
+        let Some(place_id) = item.place_id else {
+            continue;
+        };
+
+        let Some((url, page_title)) = places.get(&place_id) else {
+            continue;
+        };
+
+        // place: URLs are Firefox saved searches, not real links
+        if url.starts_with("place:") {
+            continue;
+        }
+
+        // Skip the entries that make up the tags tree
+        if is_under(&items, item.parent, tags_root) {
+            continue;
+        }
+
+        let title = item.title
+            .clone()
+            .filter(|t| !t.is_empty())
+            .or_else(|| page_title.clone().filter(|t| !t.is_empty()))
+            .unwrap_or_else(|| url.clone());
+
+        bookmarks.push(ParsedBookmark {
+            title,
+            url: url.clone(),
+            folder_path: folder_path(&items, &roots, item.parent),
+            tags: place_tags.get(&place_id).cloned().unwrap_or_default(),
+            keyword: keywords.get(&place_id).cloned(),
+            // dateAdded is in microseconds
+            added_at: item.date_added.and_then(DateTime::from_timestamp_micros),
+        });
+    }
+
+    Ok(bookmarks)
+}
+
+
+fn read_keywords(conn: &Connection) -> HashMap<i64, String> {
+
+    let mut keywords = HashMap::new();
+
+    let Ok(mut stmt) = conn.prepare("SELECT place_id, keyword FROM moz_keywords") else {
+        return keywords;
+    };
+
+    let Ok(rows) = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))) else {
+        return keywords;
+    };
+
+    for (place_id, keyword) in rows.flatten() {
+        keywords.insert(place_id, keyword);
+    }
+
+    keywords
+}
+
+
+// Folder names from the top down, built-in roots don't count
+fn folder_path(items: &HashMap<i64, Item>, roots: &HashSet<i64>, mut current: Option<i64>) -> Vec<String> {
+
+    let mut path = Vec::new();
+
+    // Depth guard in case the tree is corrupted
+    for _ in 0..256 {
+        let Some(id) = current.filter(|id| !roots.contains(id)) else {
+            break;
+        };
+
+        let Some(folder) = items.get(&id).filter(|i| i.kind == TYPE_FOLDER) else {
+            break;
+        };
+
+        let title = folder.title.clone().filter(|t| !t.is_empty());
+        path.push(title.unwrap_or_else(|| "Untitled".to_string()));
+
+        current = folder.parent;
+    }
+
+    path.reverse();
+    path
+}
+
+
+fn is_under(items: &HashMap<i64, Item>, mut current: Option<i64>, ancestor: Option<i64>) -> bool {
+
+    let Some(ancestor) = ancestor else {
+        return false;
+    };
+
+    // Depth guard in case the tree is corrupted
+    for _ in 0..256 {
+        match current {
+            Some(id) if id == ancestor => return true,
+            Some(id) => current = items.get(&id).and_then(|i| i.parent),
+            None => return false,
+        }
+    }
+
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal places.sqlite: menu > Dev > Rust holding one tagged bookmark with a keyword
+    fn fixture() -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("places-fixture-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_places (id INTEGER PRIMARY KEY, url TEXT, title TEXT);
+             CREATE TABLE moz_bookmarks (id INTEGER PRIMARY KEY, type INTEGER, fk INTEGER, parent INTEGER, title TEXT, dateAdded INTEGER, guid TEXT);
+             CREATE TABLE moz_keywords (id INTEGER PRIMARY KEY, keyword TEXT, place_id INTEGER);
+
+             INSERT INTO moz_places VALUES (1, 'https://www.rust-lang.org/', 'Rust Programming Language');
+             INSERT INTO moz_places VALUES (2, 'https://example.com/', 'Example');
+             INSERT INTO moz_places VALUES (3, 'place:sort=8&maxResults=10', 'Most Visited');
+
+             INSERT INTO moz_bookmarks VALUES (1, 2, NULL, 0, '', NULL, 'root________');
+             INSERT INTO moz_bookmarks VALUES (2, 2, NULL, 1, 'menu', NULL, 'menu________');
+             INSERT INTO moz_bookmarks VALUES (3, 2, NULL, 1, 'tags', NULL, 'tags________');
+             INSERT INTO moz_bookmarks VALUES (4, 2, NULL, 1, 'toolbar', NULL, 'toolbar_____');
+             INSERT INTO moz_bookmarks VALUES (10, 2, NULL, 2, 'Dev', NULL, 'folderDev___');
+             INSERT INTO moz_bookmarks VALUES (11, 2, NULL, 10, 'Rust', NULL, 'folderRust__');
+             INSERT INTO moz_bookmarks VALUES (20, 1, 1, 11, 'Rust', 1700000000000000, 'bookmarkRust');
+             INSERT INTO moz_bookmarks VALUES (21, 1, 2, 4, NULL, NULL, 'bookmarkEx__');
+             INSERT INTO moz_bookmarks VALUES (22, 1, 3, 4, 'Most Visited', NULL, 'smartFolder_');
+             INSERT INTO moz_bookmarks VALUES (30, 2, NULL, 3, 'lang', NULL, 'tagLang_____');
+             INSERT INTO moz_bookmarks VALUES (31, 1, 1, 30, NULL, NULL, 'tagLangRust_');
+             INSERT INTO moz_bookmarks VALUES (32, 2, NULL, 3, 'systems', NULL, 'tagSystems__');
+             INSERT INTO moz_bookmarks VALUES (33, 1, 1, 32, NULL, NULL, 'tagSysRust__');
+
+             INSERT INTO moz_keywords VALUES (1, 'rs', 1);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        bytes
+    }
+
+    #[test]
+    fn sqlite_files_are_detected() {
+        assert!(is_places_sqlite(&fixture()));
+        assert!(!is_places_sqlite(b"<!DOCTYPE NETSCAPE-Bookmark-file-1>"));
+    }
+
+    #[test]
+    fn bookmarks_keep_folders_tags_keywords_and_dates() {
+        let bookmarks = parse_places_sqlite(&fixture()).unwrap();
+
+        // The tag entries and the place: query are not bookmarks
+        assert_eq!(bookmarks.len(), 2);
+
+        let rust = &bookmarks[0];
+        assert_eq!(rust.url, "https://www.rust-lang.org/");
+        assert_eq!(rust.title, "Rust");
+        assert_eq!(rust.folder_path, vec!["Dev", "Rust"]);
+        assert_eq!(rust.tags, vec!["lang", "systems"]);
+        assert_eq!(rust.keyword.as_deref(), Some("rs"));
+        assert_eq!(rust.added_at, DateTime::from_timestamp(1_700_000_000, 0));
+    }
+
+    #[test]
+    fn missing_titles_fall_back_to_the_page_title() {
+        let bookmarks = parse_places_sqlite(&fixture()).unwrap();
+
+        let example = &bookmarks[1];
+        assert_eq!(example.title, "Example");
+        assert!(example.folder_path.is_empty());
+        assert!(example.tags.is_empty());
+        assert_eq!(example.keyword, None);
+        assert_eq!(example.added_at, None);
+    }
+
+    #[test]
+    fn other_sqlite_databases_are_rejected() {
+        let path = std::env::temp_dir().join(format!("places-fixture-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);").unwrap();
+        drop(conn);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(parse_places_sqlite(&bytes).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::utils::escape;
+
+// Words of a search query worth highlighting, lowercased. Negated words
+// ("-draft") are left out, quotes of phrases are dropped
+pub fn search_terms(q: &str) -> Vec<String> {
+
+    let mut terms: Vec<String> = Vec::new();
+
+    for word in q.split_whitespace().filter(|w| !w.starts_with('-')) {
+        for term in word.split(|c: char| !c.is_alphanumeric()).filter(|t| !t.is_empty()) {
+            let term = term.to_lowercase();
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+
+    terms
+}
+
+
+// Text with the words starting with a term wrapped in <mark>, HTML escaped.
+// With max_chars, only a window of about that size around the first match is
+// kept. None when no word matches
+pub fn highlight(text: &str, terms: &[String], max_chars: Option<usize>) -> Option<String> {
+
+    // Byte ranges of the matching words
+    let mut matches: Vec<(usize, usize)> = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                let word = text[s..i].to_lowercase();
+                if terms.iter().any(|t| word.starts_with(t.as_str())) {
+                    matches.push((s, i));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let first = matches.first()?.0;
+
+    let (from, to) = match max_chars {
+        Some(max) if text.chars().count() > max => snippet_window(text, first, max),
+        _ => (0, text.len()),
+    };
+
+    let mut out = String::new();
+    if from > 0 {
+        out.push('…');
+    }
+
+    let mut position = from;
+    for (s, e) in matches.into_iter().filter(|(s, e)| *s >= from && *e <= to) {
+        out.push_str(&escape::xml(&text[position..s]));
+        out.push_str("<mark>");
+        out.push_str(&escape::xml(&text[s..e]));
+        out.push_str("</mark>");
+        position = e;
+    }
+    out.push_str(&escape::xml(&text[position..to]));
+
+    if to < text.len() {
+        out.push('…');
+    }
+
+    Some(out)
+}
+
+
+// Byte range of about max chars starting a little before the first match,
+// cut on whitespace when possible
+fn snippet_window(text: &str, first: usize, max: usize) -> (usize, usize) {
+
+    let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).chain(std::iter::once(text.len())).collect();
+    let first_char = boundaries.iter().position(|b| *b >= first).unwrap_or(0);
+
+    let start_char = first_char.saturating_sub(max / 4);
+    let end_char = (start_char + max).min(boundaries.len() - 1);
+
+    let mut from = boundaries[start_char];
+    let mut to = boundaries[end_char];
+
+    // Whitespace can be more than one byte, like the ideographic space
+    if from > 0 {
+        if let Some((space, c)) = text[from..first].char_indices().find(|(_, c)| c.is_whitespace()) {
+            from += space + c.len_utf8();
+        }
+    }
+    if to < text.len() {
+        if let Some(space) = text[first..to].rfind(char::is_whitespace) {
+            to = first + space;
+        }
+    }
+
+    (from, to)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(q: &str) -> Vec<String> {
+        search_terms(q)
+    }
+
+    #[test]
+    fn search_terms_skip_negated_words_and_quotes() {
+        assert_eq!(search_terms("\"Rust async\" -draft tokio"), vec!["rust", "async", "tokio"]);
+        assert_eq!(search_terms("rust RUST"), vec!["rust"]);
+    }
+
+    #[test]
+    fn highlights_words_starting_with_a_term() {
+        assert_eq!(
+            highlight("Async Rust & Tokio", &terms("rust tok"), None).as_deref(),
+            Some("Async <mark>Rust</mark> &amp; <mark>Tokio</mark>"),
+        );
+        assert_eq!(highlight("Trusty", &terms("rust"), None), None);
+    }
+
+    #[test]
+    fn short_text_is_not_cut() {
+        assert_eq!(
+            highlight("learn rust", &terms("rust"), Some(50)).as_deref(),
+            Some("learn <mark>rust</mark>"),
+        );
+    }
+
+    #[test]
+    fn snippet_cuts_on_whitespace_around_the_first_match() {
+        let text = "one two three four five six seven eight nine rust ten eleven twelve thirteen fourteen";
+        let snippet = highlight(text, &terms("rust"), Some(24)).unwrap();
+
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'), "{}", snippet);
+        assert!(snippet.contains("<mark>rust</mark>"), "{}", snippet);
+        // Whole words only
+        let inner = snippet.trim_matches('…');
+        assert!(text.contains(&inner.replace("<mark>", "").replace("</mark>", "")), "{}", snippet);
+        assert!(!inner.starts_with(' ') && !inner.ends_with(' '), "{}", snippet);
+    }
+
+    #[test]
+    fn snippet_at_the_start_has_no_leading_ellipsis() {
+        let text = "rust is a language empowering everyone to build reliable and efficient software";
+        let snippet = highlight(text, &terms("rust"), Some(20)).unwrap();
+
+        assert!(snippet.starts_with("<mark>rust</mark>"), "{}", snippet);
+        assert!(snippet.ends_with('…'), "{}", snippet);
+    }
+
+    #[test]
+    fn multibyte_text_is_cut_on_char_boundaries() {
+        let text = "日本語のテキスト、とても長い説明文です。ここに Rust という単語があります。さらに続く文章がたくさんあります。";
+        let snippet = highlight(text, &terms("rust"), Some(16)).unwrap();
+        assert!(snippet.contains("<mark>Rust</mark>"), "{}", snippet);
+
+        let text = "été à la plage, puis café crème avec Zoë et une longue description française";
+        let snippet = highlight(text, &terms("zoë"), Some(20)).unwrap();
+        assert!(snippet.contains("<mark>Zoë</mark>"), "{}", snippet);
+    }
+
+    #[test]
+    fn multibyte_whitespace_is_a_boundary() {
+        // U+3000 ideographic space is three bytes long
+        let text = "一\u{3000}二\u{3000}三\u{3000}四\u{3000}五\u{3000}六\u{3000}rust\u{3000}七\u{3000}八\u{3000}九\u{3000}十";
+        let snippet = highlight(text, &terms("rust"), Some(8)).unwrap();
+        assert!(snippet.contains("<mark>rust</mark>"), "{}", snippet);
+    }
+}
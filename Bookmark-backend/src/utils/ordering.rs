@@ -0,0 +1,413 @@
+use axum::http::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::{FindOptions, FindOneOptions};
+use mongodb::{ClientSession, Collection};
+use futures::TryStreamExt;
+use serde::Deserialize;
+
+// Sort positions are strings that compare in order, so an item can always
+// get a key between two others without renumbering its neighbours.
+// They compare byte by byte and must never be sorted with a collation.
+// A key is a fixed width base 62 integer, plus a fraction when squeezed between
+// two neighbouring integers, e.g. "V0000000" < "V0000000V" < "V0000001"
+const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const BASE: u64 = 62;
+const HEAD_LEN: usize = 8;
+
+// Key of the first item in an empty list, halfway through the integer range
+const FIRST_HEAD: u64 = 31 * 62u64.pow(HEAD_LEN as u32 - 1);
+
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    // Newest first
+    CreatedAt,
+    Title,
+    Manual,
+}
+
+// Sort of a bookmark query, title sorts are case sensitive like Mongo.
+// The manual order is the one of the given collection, or of unfiled bookmarks.
+// Two appends at the same moment can get the same key, _id keeps their order stable
+pub fn bookmark_sort(sort: SortOrder, collection_id: Option<ObjectId>) -> Document {
+    match sort {
+        SortOrder::CreatedAt => doc! { "created_at": -1 },
+        SortOrder::Title => doc! { "title": 1 },
+        SortOrder::Manual => doc! { bookmark_position_field(collection_id): 1, "created_at": 1, "_id": 1 },
+    }
+}
+
+
+fn digit_value(c: u8) -> u64 {
+    DIGITS.iter().position(|d| *d == c).unwrap_or(0) as u64
+}
+
+fn encode_head(mut value: u64) -> String {
+    let mut out = vec![b'0'; HEAD_LEN];
+    for slot in out.iter_mut().rev() {
+        *slot = DIGITS[(value % BASE) as usize];
+        value /= BASE;
+    }
+    String::from_utf8(out).unwrap()
+}
+
+// Splits a key into its integer head and fraction, None for malformed keys
+fn split_key(key: &str) -> Option<(u64, &str)> {
+    if key.len() < HEAD_LEN || !key.bytes().all(|c| DIGITS.contains(&c)) {
+        return None;
+    }
+    let head = key[..HEAD_LEN].bytes().fold(0, |acc, c| acc * BASE + digit_value(c));
+    Some((head, &key[HEAD_LEN..]))
+}
+
+
+// Key sorting strictly between a and b, None meaning the start or end of the list.
+// Equal keys, left by two appends at once, have nothing between them: the key
+// then sorts right after a, and the _id tie-break orders the rest
+pub fn key_between(a: Option<&str>, b: Option<&str>) -> String {
+
+    let b = b.filter(|b| a.is_none_or(|a| a < *b));
+
+    let a = a.and_then(split_key);
+    let b = b.and_then(split_key);
+
+    match (a, b) {
+        (None, None) => encode_head(FIRST_HEAD),
+        // Appending and prepending only touch the integer, keys stay short
+        (Some((head, _)), None) => encode_head(head + 1),
+        (None, Some((head, fraction))) => {
+            if head > 0 {
+                encode_head(head - 1)
+            } else {
+                encode_head(0) + &midpoint(b"", Some(fraction.as_bytes()))
+            }
+        }
+        (Some((head_a, fraction_a)), Some((head_b, fraction_b))) => {
+            if head_b > head_a + 1 {
+                encode_head(head_a + 1)
+            } else if head_b == head_a {
+                encode_head(head_a) + &midpoint(fraction_a.as_bytes(), Some(fraction_b.as_bytes()))
+            } else {
+                encode_head(head_a) + &midpoint(fraction_a.as_bytes(), None)
+            }
+        }
+    }
+}
+
+
+// Fraction between a and b (None is 1), never ending in a zero digit
+fn midpoint(a: &[u8], b: Option<&[u8]>) -> String {
+
+    if let Some(b) = b {
+        // Copy the common prefix, a is padded with zeros
+        let mut n = 0;
+        while n < b.len() && a.get(n).copied().unwrap_or(b'0') == b[n] {
+            n += 1;
+        }
+        if n > 0 {
+            let prefix = String::from_utf8(b[..n].to_vec()).unwrap();
+            return prefix + &midpoint(a.get(n..).unwrap_or(&[]), Some(&b[n..]));
+        }
+    }
+
+    let digit_a = a.first().map(|c| digit_value(*c)).unwrap_or(0);
+    let digit_b = b.and_then(|b| b.first()).map(|c| digit_value(*c)).unwrap_or(BASE);
+
+    if digit_b - digit_a > 1 {
+        return (DIGITS[((digit_a + digit_b) / 2) as usize] as char).to_string();
+    }
+
+    // Adjacent digits, b's first digit alone already sorts between them
+    if let Some(b) = b.filter(|b| b.len() > 1) {
+        return (b[0] as char).to_string();
+    }
+
+    (DIGITS[digit_a as usize] as char).to_string() + &midpoint(a.get(1..).unwrap_or(&[]), None)
+}
+
+
+fn db_error(_: mongodb::error::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string())
+}
+
+
+// A list with its own manual order: the documents it holds, and the field
+// their key for this list is in. Collections keep one key in "position",
+// bookmarks one per collection they belong to in "positions.<collection id>"
+#[derive(Debug, Clone)]
+pub struct ListScope {
+    pub filter: Document,
+    pub field: String,
+}
+
+impl ListScope {
+    // Key of a document in this list, the field may be a dotted path
+    fn key_of<'a>(&self, item: &'a Document) -> Option<&'a str> {
+        let mut parts = self.field.split('.').peekable();
+        let mut current = item;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                return current.get_str(part).ok();
+            }
+            current = current.get_document(part).ok()?;
+        }
+        None
+    }
+
+    fn with(&self, key: &str, value: impl Into<mongodb::bson::Bson>) -> Document {
+        let mut filter = self.filter.clone();
+        filter.insert(key, value);
+        filter
+    }
+}
+
+
+// Key after the last positioned item of a list
+pub async fn append_position(
+    col: &Collection<Document>,
+    scope: &ListScope,
+) -> Result<String, (StatusCode, String)> {
+
+    let last = col
+        .find_one(
+            scope.with(&scope.field, doc! { "$exists": true }),
+            FindOneOptions::builder().sort(doc! { &scope.field: -1, "_id": -1 }).build(),
+        )
+        .await
+        .map_err(db_error)?;
+
+    Ok(key_between(last.as_ref().and_then(|d| scope.key_of(d)), None))
+}
+
+
+// Same as append_position, inside a session
+pub async fn append_position_with_session(
+    col: &Collection<Document>,
+    scope: &ListScope,
+    session: &mut ClientSession,
+) -> Result<String, String> {
+
+    let last = col
+        .find_one_with_session(
+            scope.with(&scope.field, doc! { "$exists": true }),
+            FindOneOptions::builder().sort(doc! { &scope.field: -1, "_id": -1 }).build(),
+            session,
+        )
+        .await
+        .map_err(|_| "Database error".to_string())?;
+
+    Ok(key_between(last.as_ref().and_then(|d| scope.key_of(d)), None))
+}
+
+
+// Gives every item of a list a position, in the order it was shown so far,
+// the first time a list without positions is reordered. Later moves only touch
+// one item. Items without a position sort first, then by legacy_sort
+pub async fn backfill_positions(
+    col: &Collection<Document>,
+    scope: &ListScope,
+    legacy_sort: &str,
+) -> Result<(), (StatusCode, String)> {
+
+    let missing = scope.with(&scope.field, doc! { "$exists": false });
+
+    if col.count_documents(missing, None).await.map_err(db_error)? == 0 {
+        return Ok(());
+    }
+
+    let cursor = col
+        .find(
+            scope.filter.clone(),
+            FindOptions::builder()
+                .sort(doc! { &scope.field: 1, legacy_sort: 1, "_id": 1 })
+                .projection(doc! { "_id": 1 })
+                .build(),
+        )
+        .await
+        .map_err(db_error)?;
+
+    let items: Vec<Document> = cursor.try_collect().await.map_err(db_error)?;
+
+    let mut position: Option<String> = None;
+
+    for item in items {
+        let Ok(id) = item.get_object_id("_id") else {
+            continue;
+        };
+
+        let key = key_between(position.as_deref(), None);
+
+        col.update_one(
+            doc! { "_id": id },
+            doc! { "$set": { &scope.field: &key } },
+            None,
+        )
+        .await
+        .map_err(db_error)?;
+
+        position = Some(key);
+    }
+
+    Ok(())
+}
+
+
+// Key placing an item right after another one of the list, or first when
+// after is None. The moved item itself is ignored as a neighbour
+pub async fn position_after(
+    col: &Collection<Document>,
+    scope: &ListScope,
+    legacy_sort: &str,
+    item_id: ObjectId,
+    after: Option<ObjectId>,
+) -> Result<String, (StatusCode, String)> {
+
+    backfill_positions(col, scope, legacy_sort).await?;
+
+    let previous = match after {
+        Some(after_id) => {
+            let after_doc = col
+                .find_one(scope.with("_id", after_id), None)
+                .await
+                .map_err(db_error)?
+                .ok_or((StatusCode::BAD_REQUEST, "after_id is not in the same list".to_string()))?;
+
+            Some(scope.key_of(&after_doc).unwrap_or_default().to_string())
+        }
+        None => None,
+    };
+
+    let mut filter = scope.with("_id", doc! { "$ne": item_id });
+    if let Some(previous) = &previous {
+        filter.insert(&scope.field, doc! { "$gt": previous });
+    }
+
+    let next = col
+        .find_one(filter, FindOneOptions::builder().sort(doc! { &scope.field: 1, "_id": 1 }).build())
+        .await
+        .map_err(db_error)?;
+
+    let next = next.as_ref().and_then(|d| scope.key_of(d));
+
+    Ok(key_between(previous.as_deref(), next))
+}
+
+
+// Name of a bookmark list in Bookmark.positions
+pub fn bookmark_list_key(collection_id: Option<ObjectId>) -> String {
+    match collection_id {
+        Some(id) => id.to_hex(),
+        None => "unfiled".to_string(),
+    }
+}
+
+pub fn bookmark_position_field(collection_id: Option<ObjectId>) -> String {
+    format!("positions.{}", bookmark_list_key(collection_id))
+}
+
+
+// Bookmarks sharing one manual order: those of a collection, or the user's unfiled ones
+pub fn bookmark_scope(user_id: ObjectId, collection_id: Option<ObjectId>) -> ListScope {
+    let filter = match collection_id {
+        Some(id) => doc! { "collection_ids": id },
+        None => doc! { "user_id": user_id, "collection_ids": { "$size": 0 } },
+    };
+    ListScope { filter, field: bookmark_position_field(collection_id) }
+}
+
+
+// Collections sharing one manual order: the children of a parent, or the top level
+pub fn collection_scope(user_id: ObjectId, parent_id: Option<ObjectId>) -> ListScope {
+    let filter = match parent_id {
+        Some(id) => doc! { "user_id": user_id, "parent_id": id },
+        None => doc! { "user_id": user_id, "parent_id": { "$exists": false } },
+    };
+    ListScope { filter, field: "position".to_string() }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn between(a: Option<&str>, b: Option<&str>) -> String {
+        let key = key_between(a, b);
+        assert!(split_key(&key).is_some(), "malformed key {}", key);
+        assert!(!key[HEAD_LEN..].ends_with('0'), "{} ends in a zero digit", key);
+        if let Some(a) = a {
+            assert!(a < key.as_str(), "{} is not after {}", key, a);
+        }
+        if let Some(b) = b {
+            assert!(key.as_str() < b, "{} is not before {}", key, b);
+        }
+        key
+    }
+
+    #[test]
+    fn first_key_is_halfway() {
+        assert_eq!(key_between(None, None), "V0000000");
+    }
+
+    #[test]
+    fn append_and_prepend_only_touch_the_integer() {
+        assert_eq!(between(Some("V0000000"), None), "V0000001");
+        assert_eq!(between(Some("V000000z"), None), "V0000010");
+        assert_eq!(between(None, Some("V0000000")), "Uzzzzzzz");
+        assert_eq!(between(Some("V0000000V"), None), "V0000001");
+    }
+
+    #[test]
+    fn prepend_before_the_lowest_integer_uses_a_fraction() {
+        assert_eq!(between(None, Some("00000000V")), "00000000F");
+        assert_eq!(between(None, Some("000000001")), "000000000V");
+    }
+
+    #[test]
+    fn adjacent_integers_get_a_fraction() {
+        assert_eq!(between(Some("V0000000"), Some("V0000001")), "V0000000V");
+        assert_eq!(between(Some("V0000000"), Some("V0000002")), "V0000001");
+    }
+
+    #[test]
+    fn adjacent_digits_go_one_level_deeper() {
+        assert_eq!(between(Some("V0000000A"), Some("V0000000B")), "V0000000AV");
+        assert_eq!(between(Some("V0000000A"), Some("V0000000BV")), "V0000000B");
+        assert_eq!(between(Some("V0000000"), Some("V00000001")), "V00000000V");
+    }
+
+    #[test]
+    fn repeated_inserts_stay_ordered() {
+        // Always squeezing in right after the same key
+        let low = "V0000000".to_string();
+        let mut high = "V0000001".to_string();
+        for _ in 0..200 {
+            high = between(Some(&low), Some(&high));
+        }
+
+        // And right before the same key
+        let mut low = "V0000000".to_string();
+        let high = "V0000001".to_string();
+        for _ in 0..200 {
+            low = between(Some(&low), Some(&high));
+        }
+    }
+
+    #[test]
+    fn equal_keys_sort_after_a() {
+        assert_eq!(key_between(Some("V0000000"), Some("V0000000")), "V0000001");
+        assert_eq!(key_between(Some("V0000000A"), Some("V0000000A")), "V0000001");
+    }
+
+    #[test]
+    fn keys_out_of_order_do_not_panic() {
+        let key = key_between(Some("V00000002"), Some("V00000001"));
+        assert!(key.as_str() > "V00000002");
+    }
+
+    #[test]
+    fn malformed_keys_count_as_missing() {
+        assert_eq!(key_between(Some("bad key"), None), "V0000000");
+        assert_eq!(key_between(None, Some("short")), "V0000000");
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+
+use axum::http::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::{AggregateOptions, InsertManyOptions};
+use mongodb::Database;
+use futures::TryStreamExt;
+use chrono::Utc;
+
+use crate::models::tag::{Tag, TagSettings};
+
+
+// Cleans up one tag: trims, drops leading #, collapses inner whitespace,
+// drops empty levels of a / path, lowercases if asked and cuts it to the
+// maximum length. Aliases are not applied here, None when nothing is left
+pub fn clean_tag(raw: &str, settings: &TagSettings) -> Option<String> {
+
+    let stripped = raw.trim().trim_start_matches('#');
+    let mut tag = stripped
+        .split('/')
+        .map(|level| level.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|level| !level.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if settings.lowercase {
+        tag = tag.to_lowercase();
+    }
+
+    if tag.chars().count() > settings.max_length as usize {
+        tag = tag.chars().take(settings.max_length as usize).collect::<String>().trim_end_matches([' ', '/']).to_string();
+    }
+
+    if tag.is_empty() { None } else { Some(tag) }
+}
+
+
+// Cleans a tag and resolves it through the user's aliases
+pub fn normalize_tag(raw: &str, settings: &TagSettings) -> Option<String> {
+
+    let tag = clean_tag(raw, settings)?;
+
+    Some(settings.aliases.get(&tag).cloned().unwrap_or(tag))
+}
+
+
+// Normalizes the tags of a bookmark, keeping the first of any duplicates
+pub fn normalize_tags(tags: &[String], settings: &TagSettings) -> Vec<String> {
+
+    let mut normalized: Vec<String> = Vec::new();
+
+    for tag in tags.iter().filter_map(|t| normalize_tag(t, settings)) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    normalized
+}
+
+
+// Splits comma separated tags into included and excluded ones, normalized.
+// Excluded tags start with -, e.g. "rust,async,-archived"
+pub fn parse_tag_terms(terms: &str, settings: &TagSettings) -> (Vec<String>, Vec<String>) {
+
+    let mut included = Vec::new();
+    let mut excluded = Vec::new();
+
+    for term in terms.split(',') {
+        match term.trim().strip_prefix('-') {
+            Some(tag) => excluded.extend(normalize_tag(tag, settings)),
+            None => included.extend(normalize_tag(term, settings)),
+        }
+    }
+
+    (included, excluded)
+}
+
+
+// Tag settings of a user, the defaults if they never changed them
+pub async fn load_tag_settings(db: &Database, user_id: ObjectId) -> Result<TagSettings, (StatusCode, String)> {
+
+    let settings = db.collection::<TagSettings>("tag_settings")
+        .find_one(doc! { "user_id": user_id }, None)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()))?;
+
+    Ok(settings.unwrap_or_else(|| TagSettings::defaults(user_id)))
+}
+
+
+// Normalizes the stored tags of the matching bookmarks with their owner's settings.
+// The distinct tags of each user are normalized here, then one pipeline
+// update_many swaps them in on all of that user's bookmarks at once.
+// Returns the number of bookmarks changed
+pub async fn normalize_stored_tags(db: &Database, filter: Document) -> Result<u64, mongodb::error::Error> {
+
+    let bookmarks = db.collection::<Document>("bookmarks");
+    let settings_col = db.collection::<TagSettings>("tag_settings");
+
+    let pipeline = vec![
+        doc! { "$match": filter.clone() },
+        doc! { "$unwind": "$tags" },
+        doc! { "$group": { "_id": "$user_id", "tags": { "$addToSet": "$tags" } } },
+    ];
+
+    let mut cursor = bookmarks
+        .aggregate(pipeline, AggregateOptions::builder().allow_disk_use(true).build())
+        .await?;
+
+    let mut updated = 0;
+
+    while let Some(user) = cursor.try_next().await? {
+
+        let (Ok(user_id), Ok(stored)) = (user.get_object_id("_id"), user.get_array("tags")) else {
+            continue;
+        };
+
+        let settings = settings_col
+            .find_one(doc! { "user_id": user_id }, None)
+            .await?
+            .unwrap_or_else(|| TagSettings::defaults(user_id));
+
+        // Tags are compared as literals, one starting with $ is not a field path
+        let mut branches = Vec::new();
+        let mut changed = Vec::new();
+
+        for tag in stored.iter().filter_map(|t| t.as_str()) {
+            let normalized = normalize_tag(tag, &settings);
+            if normalized.as_deref() == Some(tag) {
+                continue;
+            }
+            branches.push(doc! {
+                "case": { "$eq": ["$$tag", { "$literal": tag }] },
+                "then": { "$literal": normalized.map(Bson::String).unwrap_or(Bson::Null) }
+            });
+            changed.push(tag);
+        }
+
+        let mapped = if branches.is_empty() {
+            Bson::String("$$tag".to_string())
+        } else {
+            Bson::Document(doc! { "$switch": { "branches": branches, "default": "$$tag" } })
+        };
+
+        // Dropped tags become null, and the first of any duplicates is kept
+        let tags = doc! { "$reduce": {
+            "input": { "$map": { "input": "$tags", "as": "tag", "in": mapped } },
+            "initialValue": [],
+            "in": { "$cond": [
+                { "$or": [{ "$eq": ["$$this", null] }, { "$in": ["$$this", "$$value"] }] },
+                "$$value",
+                { "$concatArrays": ["$$value", ["$$this"]] }
+            ] }
+        } };
+
+        // Bookmarks with a changed tag, or with the same tag twice
+        let user_filter = doc! { "$and": [
+            filter.clone(),
+            { "user_id": user_id },
+            { "$or": [
+                { "tags": { "$in": changed } },
+                { "$expr": { "$ne": [
+                    { "$size": { "$ifNull": ["$tags", []] } },
+                    { "$size": { "$setUnion": [{ "$ifNull": ["$tags", []] }, []] } }
+                ] } }
+            ] }
+        ] };
+
+        let result = bookmarks
+            .update_many(user_filter, vec![doc! { "$set": { "tags": tags } }], None)
+            .await?;
+
+        updated += result.modified_count;
+    }
+
+    Ok(updated)
+}
+
+
+// Records tags in the tags collection, keeping the metadata of known ones
+pub async fn register_tags(db: &Database, user_id: ObjectId, tags: &[String]) -> Result<(), mongodb::error::Error> {
+
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let tags_col = db.collection::<Tag>("tags");
+
+    let known: HashSet<String> = tags_col
+        .distinct("name", doc! { "user_id": user_id, "name": { "$in": tags } }, None)
+        .await?
+        .into_iter()
+        .filter_map(|name| name.as_str().map(str::to_string))
+        .collect();
+
+    let now = Utc::now();
+    let mut seen = HashSet::new();
+
+    let new_tags: Vec<Tag> = tags
+        .iter()
+        .filter(|tag| !known.contains(*tag) && seen.insert(*tag))
+        .map(|tag| Tag {
+            id: None,
+            user_id,
+            name: tag.clone(),
+            color: None,
+            description: None,
+            pinned: false,
+            created_at: now,
+            updated_at: now,
+        })
+        .collect();
+
+    if new_tags.is_empty() {
+        return Ok(());
+    }
+
+    let result = tags_col
+        .insert_many(new_tags, InsertManyOptions::builder().ordered(false).build())
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        // Registered by another request in the meantime
+        Err(e) if only_duplicates(&e) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+
+fn only_duplicates(e: &mongodb::error::Error) -> bool {
+    match &*e.kind {
+        ErrorKind::BulkWrite(failure) => failure.write_concern_error.is_none()
+            && failure.write_errors.as_ref().is_some_and(|errors| errors.iter().all(|e| e.code == 11000)),
+        _ => false,
+    }
+}
+
+
+// Follows tag renames in the tags collection. When the new name already has
+// metadata it is kept and the old one dropped
+pub async fn rename_tag_metadata(
+    db: &Database,
+    user_id: ObjectId,
+    renames: &[(String, String)],
+) -> Result<(), mongodb::error::Error> {
+
+    let tags_col = db.collection::<Tag>("tags");
+
+    for (from, to) in renames.iter().filter(|(from, to)| from != to) {
+        let taken = tags_col.count_documents(doc! { "user_id": user_id, "name": to }, None).await? > 0;
+
+        if taken {
+            tags_col.delete_one(doc! { "user_id": user_id, "name": from }, None).await?;
+        } else {
+            tags_col
+                .update_one(
+                    doc! { "user_id": user_id, "name": from },
+                    doc! { "$set": { "name": to, "updated_at": Utc::now().to_rfc3339() } },
+                    None,
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> TagSettings {
+        let mut settings = TagSettings::defaults(ObjectId::new());
+        settings.lowercase = true;
+        settings.max_length = 20;
+        settings
+    }
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn clean_tag_trims_and_collapses_whitespace() {
+        let settings = settings();
+        assert_eq!(clean_tag("  #Rust  Lang ", &settings).as_deref(), Some("rust lang"));
+        assert_eq!(clean_tag(" # ", &settings), None);
+    }
+
+    #[test]
+    fn clean_tag_cleans_each_level_of_a_path() {
+        let settings = settings();
+        assert_eq!(clean_tag("Lang / Rust /  Async", &settings).as_deref(), Some("lang/rust/async"));
+        assert_eq!(clean_tag("/lang//rust/", &settings).as_deref(), Some("lang/rust"));
+        assert_eq!(clean_tag("/ / /", &settings), None);
+    }
+
+    #[test]
+    fn clean_tag_keeps_case_when_asked() {
+        let mut settings = settings();
+        settings.lowercase = false;
+        assert_eq!(clean_tag("Lang/Rust", &settings).as_deref(), Some("Lang/Rust"));
+    }
+
+    #[test]
+    fn clean_tag_cuts_on_chars_without_a_dangling_separator() {
+        let settings = settings();
+        assert_eq!(clean_tag("programming/rust/async", &settings).as_deref(), Some("programming/rust/asy"));
+        // The 20th character is a separator
+        assert_eq!(clean_tag("programming/rustlan/async", &settings).as_deref(), Some("programming/rustlan"));
+        // Multibyte characters count once
+        assert_eq!(clean_tag("日本語日本語日本語日本語日本語日本語日本語", &settings).map(|t| t.chars().count()), Some(20));
+    }
+
+    #[test]
+    fn normalize_tag_applies_aliases_to_the_cleaned_tag() {
+        let mut settings = settings();
+        settings.aliases.insert("lang/rs".to_string(), "lang/rust".to_string());
+        assert_eq!(normalize_tag(" #Lang / RS ", &settings).as_deref(), Some("lang/rust"));
+        // Only whole tags, not the levels below them
+        assert_eq!(normalize_tag("lang/rs/async", &settings).as_deref(), Some("lang/rs/async"));
+    }
+
+    #[test]
+    fn normalize_tags_keeps_the_first_of_duplicates() {
+        let mut settings = settings();
+        settings.aliases.insert("rs".to_string(), "rust".to_string());
+        assert_eq!(
+            normalize_tags(&tags(&["Lang/Rust", "rust", " lang / rust ", "RS", "", "go"]), &settings),
+            tags(&["lang/rust", "rust", "go"]),
+        );
+    }
+
+    #[test]
+    fn parse_tag_terms_splits_included_and_excluded() {
+        let (included, excluded) = parse_tag_terms("Lang/Rust, -Archived ,#async,", &settings());
+        assert_eq!(included, tags(&["lang/rust", "async"]));
+        assert_eq!(excluded, tags(&["archived"]));
+    }
+}
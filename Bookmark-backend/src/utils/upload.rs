@@ -0,0 +1,180 @@
+use std::io::{Cursor, Read};
+
+use axum::http::StatusCode;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+// How far into the document to look for a charset declaration
+const CHARSET_SCAN_BYTES: usize = 4096;
+
+
+pub fn too_large(limit: usize) -> (StatusCode, String) {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("File exceeds the {} MB upload limit", limit / (1024 * 1024)),
+    )
+}
+
+
+// Unpack gzip and zip uploads, anything else is returned as is.
+// The unpacked size is held to the same limit as the upload
+pub fn decompress(bytes: Vec<u8>, limit: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+
+    if bytes.starts_with(GZIP_MAGIC) {
+        let decoder = flate2::read::GzDecoder::new(Cursor::new(bytes));
+        return read_limited(decoder, limit);
+    }
+
+    if bytes.starts_with(ZIP_MAGIC) {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid zip file".to_string()))?;
+
+        let index = pick_zip_entry(&archive)
+            .ok_or((StatusCode::BAD_REQUEST, "No bookmarks file found in zip".to_string()))?;
+
+        let entry = archive
+            .by_index(index)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid zip file".to_string()))?;
+
+        return read_limited(entry, limit);
+    }
+
+    Ok(bytes)
+}
+
+
+// Prefer a places.sqlite, then the first HTML file
+fn pick_zip_entry(archive: &zip::ZipArchive<Cursor<Vec<u8>>>) -> Option<usize> {
+
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.name_for_index(i).unwrap_or("").to_lowercase())
+        .collect();
+
+    names
+        .iter()
+        .position(|n| n.ends_with("places.sqlite"))
+        .or_else(|| names.iter().position(|n| n.ends_with(".html") || n.ends_with(".htm")))
+}
+
+
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+
+    let mut out = Vec::new();
+
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to decompress file".to_string()))?;
+
+    if out.len() > limit {
+        return Err(too_large(limit));
+    }
+
+    Ok(out)
+}
+
+
+// Decode an HTML export using its BOM or <META charset>. Undeclared
+// files are UTF-8 when valid, otherwise Windows-1252 like old browsers wrote
+pub fn decode_html(bytes: &[u8]) -> String {
+
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        return text.into_owned();
+    }
+
+    let encoding = declared_charset(bytes).unwrap_or_else(|| {
+        if std::str::from_utf8(bytes).is_ok() { UTF_8 } else { WINDOWS_1252 }
+    });
+
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    text.into_owned()
+}
+
+
+// Finds both <meta charset="x"> and <meta content="text/html; charset=x">
+fn declared_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+
+    let head = &bytes[..bytes.len().min(CHARSET_SCAN_BYTES)];
+    let head: String = head.iter().map(|b| b.to_ascii_lowercase() as char).collect();
+
+    let mut rest = head.as_str();
+
+    while let Some(meta) = rest.find("<meta") {
+        let tag = &rest[meta..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+
+        if let Some(pos) = tag.find("charset=") {
+            let label: String = tag[pos + "charset=".len()..]
+                .trim_start_matches(['"', '\''])
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == ':')
+                .collect();
+
+            if let Some(encoding) = Encoding::for_label(label.as_bytes()) {
+                return Some(encoding);
+            }
+        }
+
+        rest = &rest[meta + "<meta".len()..];
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_bom_is_stripped() {
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice("<DT>Café".as_bytes());
+        assert_eq!(decode_html(&bytes), "<DT>Café");
+    }
+
+    #[test]
+    fn utf16_bom_wins_over_the_meta_charset() {
+        let text = "<META CHARSET=\"windows-1252\"><DT>日本";
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        assert_eq!(decode_html(&bytes), text);
+    }
+
+    #[test]
+    fn meta_charset_is_used() {
+        let mut bytes = b"<meta charset='ISO-8859-1'><DT>Caf".to_vec();
+        bytes.push(0xE9);
+        assert_eq!(decode_html(&bytes), "<meta charset='ISO-8859-1'><DT>Café");
+    }
+
+    #[test]
+    fn http_equiv_charset_is_used() {
+        let mut bytes = b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=Shift_JIS\">".to_vec();
+        // "日本" in Shift_JIS
+        bytes.extend_from_slice(&[0x93, 0xFA, 0x96, 0x7B]);
+        assert!(decode_html(&bytes).ends_with("日本"));
+    }
+
+    #[test]
+    fn unknown_charset_labels_are_skipped() {
+        let bytes = "<meta charset=\"nonsense\"><DT>Café".as_bytes();
+        assert_eq!(decode_html(bytes), "<meta charset=\"nonsense\"><DT>Café");
+    }
+
+    #[test]
+    fn undeclared_utf8_stays_utf8() {
+        let text = "<DT><A HREF=\"https://example.com\">Zoë – 日本</A>";
+        assert_eq!(decode_html(text.as_bytes()), text);
+    }
+
+    #[test]
+    fn undeclared_invalid_utf8_is_windows_1252() {
+        // Curly quotes and the euro sign sit in the 0x80-0x9F range
+        let bytes = [b'<', b'D', b'T', b'>', 0x93, b'h', b'i', 0x94, b' ', 0x80, b'5'];
+        assert_eq!(decode_html(&bytes), "<DT>\u{201C}hi\u{201D} €5");
+    }
+}
@@ -2,7 +2,7 @@
 
 import { useState } from "react"
 import { importApi } from "@/lib/api"
-import { ImportResult } from "@/types"
+import { ImportJob } from "@/types"
 import { 
     HiOutlineUpload, 
     HiOutlineDocument,
@@ -10,11 +10,18 @@ import {
     HiOutlineExclamation
 } from "react-icons/hi"
 
+// Imports run in the background, the job is polled until it finishes
+const POLL_INTERVAL_MS = 1000
+
+function isFinished(job: ImportJob) {
+    return job.status === "completed" || job.status === "failed" || job.status === "cancelled"
+}
+
 export default function ImportPage() {
 
     const [file, setFile] = useState<File | null>(null)
     const [loading, setLoading] = useState(false)
-    const [result, setResult] = useState<ImportResult | null>(null)
+    const [result, setResult] = useState<ImportJob | null>(null)
     const [error, setError] = useState("")
 
     async function handleImport(e: React.FormEvent) {
@@ -27,7 +34,19 @@ export default function ImportPage() {
 
         try {
             const res = await importApi.import(file)
-            setResult(res.data)
+            let job: ImportJob = res.data
+
+            while (!isFinished(job)) {
+                await new Promise((resolve) => setTimeout(resolve, POLL_INTERVAL_MS))
+                const poll = await importApi.job(job.id)
+                job = poll.data
+            }
+
+            if (job.status === "completed") {
+                setResult(job)
+            } else {
+                setError(job.message || `Import ${job.status}`)
+            }
         } catch (err: any) {
             setError(err.response?.data || "Import failed")
         } finally {
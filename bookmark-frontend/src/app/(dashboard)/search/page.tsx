@@ -14,6 +14,8 @@ export default function SearchPage() {
 
     const [query, setQuery] = useState("")
     const [results, setResults] = useState<Bookmark[]>([])
+    // Search results are paged, total counts all matches
+    const [total, setTotal] = useState(0)
     const [tags, setTags] = useState<Tag[]>([])
     const [loading, setLoading] = useState(false)
     const [searched, setSearched] = useState(false)
@@ -34,9 +36,11 @@ export default function SearchPage() {
 
         try {
             const res = await searchApi.search(query)
-            setResults(res.data)
+            setResults(res.data.results)
+            setTotal(res.data.total)
         } catch {
             setResults([])
+            setTotal(0)
         } finally {
             setLoading(false)
         }
@@ -51,8 +55,10 @@ export default function SearchPage() {
         try {
             const res = await tagApi.bookmarksByTag(tag)
             setResults(res.data)
+            setTotal(res.data.length)
         } catch {
             setResults([])
+            setTotal(0)
         } finally {
             setLoading(false)
         }
@@ -138,7 +144,7 @@ export default function SearchPage() {
                         </h2>
                         {!loading && (
                             <span className="text-sm text-neutral-500">
-                                {results.length} found
+                                {total} found
                             </span>
                         )}
                     </div>
@@ -21,7 +21,9 @@ export default function BookmarkForm({
     const [url, setUrl] = useState(bookmark?.url || "")
     const [description, setDescription] = useState(bookmark?.description || "")
     const [tags, setTags] = useState(bookmark?.tags.join(", ") || "")
-    const [collectionId, setCollectionId] = useState(bookmark?.collection_id || "")
+    // The form picks one collection, others the bookmark is in are kept
+    const initialCollectionId = bookmark?.collection_ids[0] || ""
+    const [collectionId, setCollectionId] = useState(initialCollectionId)
     const [collections, setCollections] = useState<Collection[]>([])
     const [loading, setLoading] = useState(false)
     const [error, setError] = useState("")
@@ -32,6 +34,15 @@ export default function BookmarkForm({
             .catch(() => {})
     }, [])
 
+    // Leaves the collections alone when the selection didn't change
+    function collectionIds(): string[] | undefined {
+        if (bookmark && collectionId === initialCollectionId) {
+            return undefined
+        }
+        const others = bookmark?.collection_ids.filter((id) => id !== initialCollectionId) ?? []
+        return collectionId ? [...others, collectionId] : others
+    }
+
     async function handleSubmit(e: React.FormEvent) {
         e.preventDefault()
         setError("")
@@ -42,7 +53,7 @@ export default function BookmarkForm({
             url,
             description: description || undefined,
             tags: tags.split(",").map((t) => t.trim()).filter(Boolean),
-            collection_id: collectionId || undefined,
+            collection_ids: collectionIds(),
         }
 
         try {
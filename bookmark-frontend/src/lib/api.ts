@@ -1,4 +1,5 @@
 import axios from "axios"
+import { SearchResponse } from "@/types"
 
 const api = axios.create({
     baseURL: process.env.NEXT_PUBLIC_API_URL || "http://localhost:3000/api",
@@ -71,7 +72,7 @@ export const tagApi = {
 // Search
 export const searchApi = {
     search: (q: string) =>
-        api.get(`/search?q=${q}`),
+        api.get<SearchResponse>(`/search?q=${encodeURIComponent(q)}`),
 }
 
 // Import
@@ -83,6 +84,9 @@ export const importApi = {
             headers: { "Content-Type": "multipart/form-data" },
         })
     },
+
+    job: (id: string) =>
+        api.get(`/import/${id}`),
 }
 
 export default api
\ No newline at end of file
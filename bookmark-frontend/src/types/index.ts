@@ -10,7 +10,7 @@ export interface Bookmark {
     url: string
     description?: string
     tags: string[]
-    collection_id?: string
+    collection_ids: string[]
     created_at: string
     updated_at: string
 }
@@ -34,7 +34,7 @@ export interface CreateBookmarkInput {
     url: string
     description?: string
     tags: string[]
-    collection_id?: string
+    collection_ids?: string[]
 }
 
 export interface UpdateBookmarkInput {
@@ -42,7 +42,7 @@ export interface UpdateBookmarkInput {
     url?: string
     description?: string
     tags?: string[]
-    collection_id?: string
+    collection_ids?: string[]
 }
 
 export interface CreateCollectionInput {
@@ -55,11 +55,38 @@ export interface UpdateCollectionInput {
     description?: string
 }
 
-export interface ImportResult {
-    message: string
+export type ImportStatus = "preview" | "pending" | "running" | "completed" | "failed" | "cancelled"
+
+export interface ImportJob {
+    id: string
+    status: ImportStatus
+    total: number
+    processed: number
     imported: number
     skipped: number
+    updated: number
     collections_created: number
+    message?: string | null
+}
+
+export interface SearchResult extends Bookmark {
+    score: number
+    // Matched terms wrapped in <mark>, only for fields with a match
+    highlights: {
+        title?: string
+        description?: string
+    }
+}
+
+export interface SearchResponse {
+    results: SearchResult[]
+    total: number
+    page: number
+    per_page: number
+    facets: {
+        tags: Tag[]
+        collections: { id: string, name: string, count: number }[]
+    }
 }
 
 export interface ApiError {